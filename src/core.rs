@@ -1,19 +1,145 @@
 pub mod build;
+pub mod events;
+pub mod file;
+pub mod graph;
+pub mod janitor;
+pub mod runtime;
+pub mod script;
+pub mod secrets;
+pub mod summary;
+#[cfg(test)]
+pub mod testing;
+pub mod validate;
+
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
 
 use derive_new::new;
 use nonempty::NonEmpty;
+use serde_derive::Deserialize;
+use tokio::time::Instant;
 
-#[derive(Debug, PartialEq, Eq, Clone, new)]
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize, new)]
+#[serde(deny_unknown_fields)]
 pub struct Pipeline {
     pub steps: NonEmpty<Step>,
+    /// Environment variables set in every step.
+    #[serde(default)]
+    #[new(default)]
+    pub env: HashMap<String, String>,
+    /// Secrets exposed to every step as environment variables of the same name.
+    #[serde(default)]
+    #[new(default)]
+    pub secrets: Vec<String>,
+    /// Deadline for the whole build, in seconds in pipeline files. Running steps
+    /// are killed once it passes and the steps left are skipped.
+    #[serde(
+        default,
+        with = "serde_with::As::<Option<serde_with::DurationSeconds<u64>>>"
+    )]
+    #[new(default)]
+    pub timeout: Option<Duration>,
+}
+
+impl Pipeline {
+    /// Every secret the pipeline or one of its steps asks for, without duplicates.
+    pub fn secret_names(&self) -> Vec<String> {
+        let mut names = self.secrets.clone();
+        for name in self.steps.iter().flat_map(|s| &s.secrets) {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        names
+    }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, new)]
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize, new)]
+#[serde(deny_unknown_fields)]
 pub struct Step {
     pub name: StepName,
     pub commands: NonEmpty<String>,
     pub image: Image,
+    #[serde(default)]
     pub depends_on: Option<Vec<StepName>>,
+    #[serde(default, rename = "pull")]
+    #[new(default)]
+    pub pull_policy: PullPolicy,
+    /// Environment variables for this step, overriding the pipeline's.
+    #[serde(default)]
+    #[new(default)]
+    pub env: HashMap<String, String>,
+    /// Secrets exposed to this step as environment variables of the same name.
+    #[serde(default)]
+    #[new(default)]
+    pub secrets: Vec<String>,
+    /// Shell the commands run in, invoked as `<shell> -c <script>`. Defaults to
+    /// [`script::DEFAULT_SHELL`].
+    #[serde(default)]
+    #[new(default)]
+    pub shell: Option<String>,
+    /// How long the step may run, in seconds in pipeline files, before its
+    /// container is killed.
+    #[serde(
+        default,
+        with = "serde_with::As::<Option<serde_with::DurationSeconds<u64>>>"
+    )]
+    #[new(default)]
+    pub timeout: Option<Duration>,
+    /// Run the step again when it fails, instead of failing it right away.
+    #[serde(default)]
+    #[new(default)]
+    pub retry: Option<RetryPolicy>,
+    /// Text fed to the step's standard input, which is closed after it.
+    #[serde(default)]
+    #[new(default)]
+    pub stdin: Option<String>,
+}
+
+/// How often and when a failed step is run again.
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first one.
+    pub max_attempts: usize,
+    /// Delay before the first retry, in seconds in pipeline files. It doubles
+    /// with every retry after that.
+    #[serde(default, with = "serde_with::As::<serde_with::DurationSeconds<u64>>")]
+    pub backoff: Duration,
+    /// Only retry when the step exits with one of these codes; any failure is
+    /// retried when empty. Timed out steps are never retried.
+    #[serde(default)]
+    pub exit_codes: Vec<i64>,
+}
+
+impl RetryPolicy {
+    /// How long to wait before the next attempt of a step that failed with
+    /// `exit` after `attempts` attempts, or `None` if it should not run again.
+    pub fn next_delay(&self, exit: &ContainerExitCode, attempts: usize) -> Option<Duration> {
+        if attempts >= self.max_attempts {
+            return None;
+        }
+        if !self.exit_codes.is_empty() && !self.exit_codes.contains(&exit.0) {
+            return None;
+        }
+        let retries = attempts.saturating_sub(1).min(16) as u32;
+        Some(self.backoff.saturating_mul(2u32.pow(retries)))
+    }
+}
+
+/// When to pull a step's image before creating its container.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PullPolicy {
+    /// Pull on every run, picking up updates to mutable tags.
+    Always,
+    /// Pull only when the image is not available locally.
+    #[default]
+    IfNotPresent,
+    /// Never pull; the image must already be available locally.
+    Never,
 }
 // impl Step {
 //     pub fn new(name: String, image: String, commands: NonEmpty<String>) -> Self {
@@ -25,7 +151,8 @@ pub struct Step {
 //     }
 // }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize)]
+#[serde(transparent)]
 pub struct StepName(pub String);
 
 impl From<&str> for StepName {
@@ -39,7 +166,45 @@ impl From<StepName> for String {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// Identifies a single run of a pipeline; every resource the build creates is
+/// named and labelled with it.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct BuildId(pub String);
+
+impl BuildId {
+    pub fn generate() -> Self {
+        BuildId(format!("{:012x}", rand::random::<u64>() >> 16))
+    }
+}
+
+impl From<&str> for BuildId {
+    fn from(value: &str) -> Self {
+        BuildId(String::from(value))
+    }
+}
+
+/// Identifies a runner, so that operators can tell which runner created a
+/// resource on a daemon shared by several. Generated for each process unless
+/// configured, in which case it is kept across restarts. No two processes may
+/// share one: the janitor takes what the runner labelled outside its own
+/// build for a crashed run's leftovers.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct RunnerId(pub String);
+
+impl RunnerId {
+    pub fn generate() -> Self {
+        RunnerId(format!("{:012x}", rand::random::<u64>() >> 16))
+    }
+}
+
+impl From<&str> for RunnerId {
+    fn from(value: &str) -> Self {
+        RunnerId(String::from(value))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+#[serde(transparent)]
 pub struct Image(pub String);
 
 impl From<&str> for Image {
@@ -54,28 +219,67 @@ impl From<Image> for String {
     }
 }
 
+impl Image {
+    /// Split the image into a repository and a tag or digest, defaulting to
+    /// `latest` so a pull never fetches every tag of the repository.
+    pub fn reference(&self) -> (String, String) {
+        if let Some((repository, digest)) = self.0.split_once('@') {
+            return (repository.to_string(), digest.to_string());
+        }
+        match self.0.rsplit_once(':') {
+            // A colon before the last `/` belongs to a registry port, not a tag.
+            Some((repository, tag)) if !tag.contains('/') => {
+                (repository.to_string(), tag.to_string())
+            }
+            _ => (self.0.clone(), "latest".to_string()),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum BuildState {
     BuildReady,
     BuildRunning(BuildRunningState),
     BuildFinished(BuildResult),
 }
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct BuildRunningState {
-    pub step: StepName,
+    /// Steps currently running, by name.
+    pub steps: HashMap<StepName, RunningStep>,
+    /// Failed steps waiting to be retried, with when the next attempt may start.
+    pub retrying: HashMap<StepName, Instant>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RunningStep {
+    /// Id of the container the step runs in.
+    pub container: String,
+    pub started: Instant,
+    /// Wall-clock time of `started`, for reporting.
+    pub started_at: SystemTime,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum BuildResult {
     BuildSucceeded,
     BuildFailed,
+    /// The build was cancelled through its cancellation token.
+    BuildCancelled,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum StepResult {
     StepFailed(ContainerExitCode),
     StepSucceeded,
     StepSkipped,
+    /// The step ran past its own timeout or the build's, and was killed.
+    StepTimedOut,
+    /// The build was cancelled while the step was running or waiting to be
+    /// retried; a running step was killed.
+    StepCancelled,
 }
 impl From<ContainerExitCode> for StepResult {
     fn from(value: ContainerExitCode) -> Self {
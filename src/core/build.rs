@@ -1,188 +1,1998 @@
 use std::{
-    borrow::{Borrow, BorrowMut},
     collections::HashMap,
+    fmt,
+    future::Future,
+    pin::pin,
+    sync::{Arc, Mutex},
+    time::SystemTime,
     vec,
 };
 
-use bollard_stubs::models::{ContainerWaitResponse, ContainerWaitResponseError};
+use bollard_stubs::models::{ContainerWaitResponse, HostConfig, Mount, MountTypeEnum};
 use derive_new::new;
-use futures_core::Stream;
-use futures_util::{future, StreamExt};
-use nonempty::NonEmpty;
+use futures_util::{
+    future::{self, Either},
+    StreamExt,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::broadcast,
+    task::JoinHandle,
+    time::{self, Instant},
+};
+use tokio_util::sync::CancellationToken;
 
 use crate::{
     docker::{
         container::{
-            CreateContainerConfig, CreateContainerOptions, StartContainerOptions,
-            WaitContainerOptions,
+            AttachContainerOptions, AttachContainerResults, CreateContainerConfig,
+            CreateContainerOptions, InspectContainerOptions, KillContainerOptions, LogsOptions,
+            RemoveContainerOptions, StartContainerOptions, WaitContainerOptions,
         },
         errors::Error,
-        Docker,
+        image::CreateImageOptions,
+        utils::LogOutput,
+        volume::{CreateVolumeOptions, RemoveVolumeOptions},
     },
-    BuildResult, BuildRunningState, BuildState, ContainerExitCode, Pipeline, Step, StepName,
-    StepResult,
+    BuildId, BuildResult, BuildRunningState, BuildState, ContainerExitCode, Pipeline, PullPolicy,
+    RunnerId, RunningStep, Step, StepName, StepResult,
 };
 
-pub type CompletedSteps = Vec<(StepName, StepResult)>;
+use super::{
+    events::{BuildEvent, EVENT_CAPACITY},
+    graph::{Readiness, StepGraph},
+    runtime::ContainerService,
+    script,
+    secrets::Secrets,
+};
+
+/// Number of steps a build runs at once unless told otherwise.
+pub const DEFAULT_MAX_PARALLELISM: usize = 4;
+
+/// Label carried by every resource the runner creates.
+pub const RUNNER_LABEL: &str = "nova";
+/// Label holding the id of the build that created a resource.
+pub const BUILD_LABEL: &str = "nova.build";
+/// Label holding the id of the runner that created a resource.
+pub const RUNNER_ID_LABEL: &str = "nova.runner";
+/// Label holding the name of the step a container runs.
+pub const STEP_LABEL: &str = "nova.step";
+
+/// Exit code recorded for a step whose container could not be started, or that
+/// the runner lost track of because waiting on it failed, as Docker reports an
+/// unknown exit code.
+const UNKNOWN_EXIT_CODE: i64 = -1;
+
+/// Where the build's shared workspace volume is mounted in every step, and the
+/// directory step commands start in.
+pub const WORKSPACE_DIR: &str = "/workspace";
+
+/// One run of a step's container.
+#[derive(Debug, PartialEq, Eq, Clone, new)]
+pub struct StepAttempt {
+    pub result: StepResult,
+    pub started: SystemTime,
+    pub finished: SystemTime,
+    /// Output captured from the container.
+    pub logs: Vec<LogOutput>,
+    /// 0-based index into the step's `commands` of the command that failed.
+    pub failed_command: Option<usize>,
+    /// Whether Docker killed the container for running out of memory.
+    #[new(default)]
+    pub oom_killed: bool,
+}
+
+/// Outcome of a step that will not run again.
 #[derive(Debug, PartialEq, Eq, Clone, new)]
+pub struct CompletedStep {
+    pub name: StepName,
+    /// Result of the last attempt.
+    pub result: StepResult,
+    /// Every run of the step, oldest first; empty for skipped steps.
+    #[new(default)]
+    pub attempts: Vec<StepAttempt>,
+}
+
+pub type CompletedSteps = Vec<CompletedStep>;
+
+/// What is left to write to a step's `stdin`, and the attached container to
+/// write it to.
+type StepInput = (String, AttachContainerResults);
+
+/// How starting a step's container ended, see [`Build::start_step`].
+#[derive(Debug)]
+enum Started {
+    /// The container runs, with what is left to write to its `stdin`.
+    Running(String, Option<StepInput>),
+    /// The build stopped before the container was started, after it was
+    /// created if given.
+    Interrupted(Option<String>),
+    /// A Docker call failed, after the container was created if given.
+    Failed(Option<String>, String),
+}
+
+/// A task following a running step's container, see [`Build::watch_step`].
+#[derive(Debug)]
+struct Watcher {
+    /// The step's output so far, redacted.
+    logs: Arc<Mutex<Vec<LogOutput>>>,
+    /// Ends with the container's exit status once it stops.
+    exit: JoinHandle<Option<Result<ContainerWaitResponse, Error>>>,
+}
+
+impl Watcher {
+    fn logs(&self) -> Vec<LogOutput> {
+        std::mem::take(&mut self.logs.lock().unwrap())
+    }
+}
+
+#[derive(Debug, new)]
 pub struct Build {
     pub pipeline: Pipeline,
     pub state: BuildState,
     pub completed_steps: CompletedSteps,
-    #[new(value = "false")]
-    pub fail_through: bool,
+    #[new(value = "DEFAULT_MAX_PARALLELISM")]
+    pub max_parallelism: usize,
+    #[new(value = "BuildId::generate()")]
+    pub id: BuildId,
+    /// The runner driving the build, a new one for each process unless given.
+    #[new(value = "RunnerId::generate()")]
+    pub runner: RunnerId,
+    /// Every container created for this build and not yet removed.
+    #[new(default)]
+    pub containers: Vec<String>,
+    /// Volume shared by every step, once created.
+    #[new(default)]
+    pub workspace: Option<String>,
+    #[new(default)]
+    pub secrets: Secrets,
+    /// When the first steps were started.
+    #[new(default)]
+    pub started: Option<Instant>,
+    /// Wall-clock time the build was first driven.
+    #[new(default)]
+    pub started_at: Option<SystemTime>,
+    /// Wall-clock time the build reached [`BuildState::BuildFinished`].
+    #[new(default)]
+    pub finished_at: Option<SystemTime>,
+    /// Attempts of the steps waiting to be retried.
+    #[new(default)]
+    attempts: HashMap<StepName, Vec<StepAttempt>>,
+    /// Tasks following the running steps.
+    #[new(default)]
+    watchers: HashMap<StepName, Watcher>,
+    /// Tasks starting the steps picked to run next.
+    #[new(default)]
+    starting: HashMap<StepName, JoinHandle<Started>>,
+    /// Cancelling it stops the build at the next call to `progress`, or during
+    /// the one in flight, even while a step's image is pulled.
+    #[new(default)]
+    pub cancellation: CancellationToken,
+    /// Cancelled once the build stops, interrupting the image pulls of the
+    /// steps being started.
+    #[new(default)]
+    stopping: CancellationToken,
+    #[new(value = "broadcast::channel(EVENT_CAPACITY).0")]
+    events: broadcast::Sender<BuildEvent>,
 }
 
 impl Build {
+    /// Run at most `max_parallelism` steps at the same time.
+    pub fn with_max_parallelism(mut self, max_parallelism: usize) -> Self {
+        self.max_parallelism = max_parallelism;
+        self
+    }
+
+    /// Secret values for the pipeline's `secrets`; they are redacted from every
+    /// captured log.
+    pub fn with_secrets(mut self, secrets: Secrets) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    /// Label the build's resources as created by `runner`, so that they are
+    /// still told apart once the process restarts.
+    pub fn with_runner(mut self, runner: RunnerId) -> Self {
+        self.runner = runner;
+        self
+    }
+
+    /// Cancel the build when `cancellation` is cancelled: running steps are
+    /// killed and recorded as [`StepResult::StepCancelled`], the steps left
+    /// are skipped and the build ends in [`BuildResult::BuildCancelled`].
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Receive every event the build emits from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<BuildEvent> {
+        self.events.subscribe()
+    }
+}
+
+impl Build {
+    /// Send an event to the subscribers, if there are any.
+    fn emit(&self, event: BuildEvent) {
+        let _ = self.events.send(event);
+    }
+
+    /// Report a failed Docker call, about `step` if given.
+    fn emit_error(&self, step: Option<&StepName>, error: impl fmt::Display) {
+        self.emit(BuildEvent::DockerError {
+            step: step.cloned(),
+            error: error.to_string(),
+            at: SystemTime::now(),
+        });
+    }
+
+    /// Record a step that will not run again.
+    fn finish_step(&mut self, completed: CompletedStep) {
+        self.emit(BuildEvent::StepFinished {
+            step: completed.name.clone(),
+            result: completed.result.clone(),
+            attempts: completed.attempts.len(),
+            at: SystemTime::now(),
+        });
+        self.completed_steps.push(completed);
+    }
+
     fn find_completed_steps(
         completed_steps: CompletedSteps,
         step_name_to_match: &StepName,
     ) -> bool {
         completed_steps
             .into_iter()
-            .find(|(step_name, res)| step_name.0 == step_name_to_match.0)
-            .is_some()
+            .any(|step| step.name.0 == step_name_to_match.0)
     }
-    fn next_step(&self) -> Option<Step> {
-        self.pipeline.steps.clone().into_iter().find(|step| {
-            Build::find_completed_steps(self.completed_steps.clone(), &step.name) == false
-        })
+    fn pending_steps(&self) -> Vec<Step> {
+        self.pipeline
+            .steps
+            .clone()
+            .into_iter()
+            .filter(|step| !Build::find_completed_steps(self.completed_steps.clone(), &step.name))
+            .collect()
     }
     fn all_steps_succeeded(&self) -> bool {
         self.completed_steps
             .clone()
             .into_iter()
-            .all(|(_, res)| res == StepResult::StepSucceeded)
+            .all(|step| step.result == StepResult::StepSucceeded)
     }
-    fn find_depends_on(completed_steps: CompletedSteps, step_depens_on: Vec<StepName>) -> bool {
-        step_depens_on
-            .into_iter()
-            .find(|s| Build::find_completed_steps(completed_steps.clone(), s))
-            .is_some()
+    /// Mark every pending step that can no longer run as skipped. Skipping a
+    /// step can make its own dependents unreachable, so repeat until stable.
+    fn skip_unreachable_steps(&mut self) {
+        let graph = StepGraph::new(&self.pipeline);
+        loop {
+            let unreachable: Vec<StepName> = self
+                .pending_steps()
+                .into_iter()
+                .filter(|step| {
+                    graph.readiness(&step.name, &self.completed_steps) == Readiness::Unreachable
+                })
+                .map(|step| step.name)
+                .collect();
+            if unreachable.is_empty() {
+                break;
+            }
+            for name in unreachable {
+                self.finish_step(CompletedStep::new(name, StepResult::StepSkipped));
+            }
+        }
+    }
+}
+
+/// What woke a running build up.
+enum Wakeup {
+    /// A step's container stopped, or waiting on it failed.
+    Exited(StepName, Option<Result<ContainerWaitResponse, Error>>),
+    /// Starting a step's container ended.
+    Started(StepName, Started),
+    TimedOut,
+    Cancelled,
+}
+
+/// The first of `futures` to finish, or none ever if there are none, as
+/// `select_all` panics on an empty list.
+async fn first<F: Future + Unpin>(futures: Vec<F>) -> F::Output {
+    if futures.is_empty() {
+        future::pending().await
+    } else {
+        future::select_all(futures).await.0
     }
 }
 
 impl Build {
-    pub async fn progress(&mut self, conn: &Docker) {
+    /// Drive the build to completion. Each step of the state machine waits on
+    /// the containers themselves, so steps start as soon as they are ready.
+    pub async fn run(&mut self, conn: &impl ContainerService) -> (BuildResult, CompletedSteps) {
+        loop {
+            if let BuildState::BuildFinished(result) = &self.state {
+                return (result.clone(), self.completed_steps.clone());
+            }
+            self.progress(conn).await;
+        }
+    }
+
+    /// Move the build forward: start it, wait for a running step to finish or
+    /// time out, and start the steps that became ready. A finished build is
+    /// left as it is, so calling this again once it is over does nothing.
+    pub async fn progress(&mut self, conn: &impl ContainerService) {
         self.completed_steps.reserve(self.pipeline.steps.len());
+        if self.started_at.is_none() {
+            let at = SystemTime::now();
+            self.started_at = Some(at);
+            self.emit(BuildEvent::BuildStarted {
+                build: self.id.clone(),
+                at,
+            });
+        }
         match self.state.clone() {
-            BuildState::BuildReady => match self.has_next_step() {
-                Ok(step) => {
-                    // println!("{:?}", &step.name);
-                    // self.state = BuildState::BuildRunning(BuildRunningState { step: step.name })
-
-                    if !self.fail_through {
-                        let commands: Vec<String> = step.commands.clone().into();
-                        let commands = commands.join(" ");
-                        let mut labels = HashMap::new();
-                        labels.insert("nova".to_string(), "".to_string());
-                        let container = conn
-                            .create_container(
-                                Some(CreateContainerOptions::new(step.name.clone().0, None)),
-                                CreateContainerConfig::new(
-                                    step.image.into(),
-                                    true,
-                                    labels,
-                                    vec!["/bin/sh".to_string(), "-c".to_string()],
-                                    commands,
-                                ),
-                            )
-                            .await;
-                        match container {
-                            Ok(container) => {
-                                let res = conn
-                                    .start_container(
-                                        &container.id,
-                                        None::<StartContainerOptions<String>>,
-                                    )
-                                    .await;
-
-                                match res {
-                                    Ok(_) => {
-                                        self.state = BuildState::BuildRunning(BuildRunningState {
-                                            step: step.name.clone(),
-                                        })
-                                    }
-                                    Err(err) => {
-                                        println!("{:?}", err);
-                                        self.state =
-                                            BuildState::BuildFinished(BuildResult::BuildFailed)
-                                    }
-                                }
-                            }
-                            Err(err) => {
-                                println!("{:?}", err);
-                                self.state = BuildState::BuildFinished(BuildResult::BuildFailed)
+            BuildState::BuildReady if self.cancellation.is_cancelled() => {
+                self.cancel(conn, &mut BuildRunningState::default()).await
+            }
+            BuildState::BuildReady => match self.check_pipeline() {
+                Ok(()) => match self.create_workspace(conn).await {
+                    Ok(()) => {
+                        self.started = Some(Instant::now());
+                        self.start_steps(conn, BuildRunningState::default())
+                    }
+                    Err(err) => {
+                        self.emit_error(None, err);
+                        self.state = BuildState::BuildFinished(BuildResult::BuildFailed);
+                        self.abandon_pending_steps();
+                    }
+                },
+                Err(reason) => {
+                    self.emit(BuildEvent::BuildRejected {
+                        reason,
+                        at: SystemTime::now(),
+                    });
+                    self.state = BuildState::BuildFinished(BuildResult::BuildFailed);
+                    self.abandon_pending_steps();
+                }
+            },
+            BuildState::BuildRunning(mut state) => {
+                let deadline = self.next_deadline(&state);
+                let exits = self.watchers.iter_mut().map(|(step, watcher)| {
+                    Box::pin(async move {
+                        // A watcher that panicked lost track of its step.
+                        (step.clone(), (&mut watcher.exit).await.unwrap_or(None))
+                    })
+                });
+                let starts = self.starting.iter_mut().map(|(step, starter)| {
+                    Box::pin(async move {
+                        let started = (&mut *starter)
+                            .await
+                            .unwrap_or_else(|err| Started::Failed(None, err.to_string()));
+                        (step.clone(), started)
+                    })
+                });
+                let timeout = async move {
+                    match deadline {
+                        Some(deadline) => time::sleep_until(deadline).await,
+                        None => future::pending().await,
+                    }
+                };
+                let cancellation = self.cancellation.clone();
+                let wakeup = tokio::select! {
+                    (step, res) = first(exits.collect()) => Wakeup::Exited(step, res),
+                    (step, started) = first(starts.collect()) => Wakeup::Started(step, started),
+                    _ = timeout => Wakeup::TimedOut,
+                    _ = cancellation.cancelled() => Wakeup::Cancelled,
+                };
+                match wakeup {
+                    Wakeup::Exited(step, res) => {
+                        let logs = self.watchers.remove(&step).map(|w| w.logs());
+                        if let Some(running) = state.steps.remove(&step) {
+                            let logs = logs.unwrap_or_default();
+                            self.handle_running_state(conn, &mut state, step, &running, logs, res)
+                                .await;
+                        }
+                    }
+                    Wakeup::Started(step, started) => {
+                        self.starting.remove(&step);
+                        self.handle_started(conn, &mut state, step, started);
+                    }
+                    Wakeup::TimedOut => self.handle_timeouts(conn, &mut state).await,
+                    Wakeup::Cancelled => self.cancel(conn, &mut state).await,
+                }
+                if !matches!(self.state, BuildState::BuildFinished(_)) {
+                    self.start_steps(conn, state)
+                }
+            }
+            BuildState::BuildFinished(_) => return,
+        }
+        if let BuildState::BuildFinished(result) = self.state.clone() {
+            let at = SystemTime::now();
+            self.finished_at = Some(at);
+            self.cleanup(conn).await;
+            self.emit(BuildEvent::BuildFinished { result, at });
+        }
+    }
+
+    /// Follow a started step from a task of its own until its container
+    /// stops, as reported by a `wait_container` request held for as long as
+    /// it runs; daemon events are only audited. The step's output is sent as
+    /// [`BuildEvent::StepLog`] as it is written, from a single logs request,
+    /// and kept for its attempt. Its `input` is written from the same task, so
+    /// a step that never reads it cannot hold up the build.
+    fn watch_step(
+        &mut self,
+        conn: &impl ContainerService,
+        step: &StepName,
+        container: &str,
+        input: Option<StepInput>,
+    ) {
+        let logs = Arc::new(Mutex::new(vec![]));
+        let (conn, name, container) = (conn.clone(), step.clone(), container.to_string());
+        let (events, secrets, lines) = (self.events.clone(), self.secrets.clone(), logs.clone());
+        let exit = tokio::spawn(async move {
+            let feed = async {
+                if let Some((stdin, mut attached)) = input {
+                    // Closing our end closes the container's `stdin`, see
+                    // `stdin_once`.
+                    let written = async {
+                        attached.input.write_all(stdin.as_bytes()).await?;
+                        attached.input.shutdown().await
+                    };
+                    if let Err(err) = written.await {
+                        let _ = events.send(BuildEvent::DockerError {
+                            step: Some(name.clone()),
+                            error: err.to_string(),
+                            at: SystemTime::now(),
+                        });
+                    }
+                }
+                // Only the container stopping ends the watcher.
+                future::pending().await
+            };
+            let follow = async {
+                let options = LogsOptions::new(true, true, true, 0, 0, false, "all".to_string());
+                let mut stream = pin!(conn.logs(&container, Some(options)));
+                while let Some(line) = stream.next().await {
+                    let event = match line {
+                        Ok(line) => {
+                            let line = secrets.redact(line);
+                            lines.lock().unwrap().push(line.clone());
+                            BuildEvent::StepLog {
+                                step: name.clone(),
+                                line,
+                                at: SystemTime::now(),
                             }
                         }
-                    } else {
-                        self.completed_steps
-                            .push((step.name.to_owned(), StepResult::StepSkipped));
-                        self.state = BuildState::BuildReady
+                        Err(err) => BuildEvent::DockerError {
+                            step: Some(name.clone()),
+                            error: err.to_string(),
+                            at: SystemTime::now(),
+                        },
+                    };
+                    let _ = events.send(event);
+                }
+            };
+            let wait = async {
+                loop {
+                    let options = WaitContainerOptions::new("not-running".to_string());
+                    match pin!(conn.wait_container(&container, Some(options)))
+                        .next()
+                        .await
+                    {
+                        // The request outlived the client timeout while the
+                        // step keeps running.
+                        Some(Err(Error::RequestTimeoutError)) => continue,
+                        exit => return exit,
                     }
                 }
-                Err(res) => self.state = BuildState::BuildFinished(res),
-            },
-            BuildState::BuildRunning(state) => {
-                let wait = conn.wait_container(
-                    &state.step.clone().0,
-                    Some(WaitContainerOptions::new("not-running")),
-                );
-                self.handle_running_state(wait, state.borrow()).await
-            }
-            BuildState::BuildFinished(_) => todo!(),
-        }
-    }
-
-    pub fn has_next_step(&self) -> Result<Step, BuildResult> {
-        // if self.all_steps_succeeded() {
-        match self.next_step() {
-            Some(step) => {
-                if let Some(ref s) = step.depends_on {
-                    // println!("{}", Build::find_depends_on(&self.completed_steps, s));
-                    // if !Build::find_depends_on(&self.completed_steps, s) {
-                    //     Ok(step)
-                    // } else {
-                    //     Err(BuildResult::BuildFailed)
-                    // }
-                    Ok(step)
-                } else {
-                    Ok(step)
+            };
+            let watch = async {
+                match future::select(pin!(wait), pin!(follow)).await {
+                    // The container stopped, so its logs end too.
+                    Either::Left((
+                        exit @ (Some(Ok(_)) | Some(Err(Error::DockerContainerWaitError { .. }))),
+                        follow,
+                    )) => {
+                        follow.await;
+                        exit
+                    }
+                    Either::Left((exit, _)) => exit,
+                    Either::Right(((), wait)) => wait.await,
                 }
+            };
+            tokio::select! {
+                exit = watch => exit,
+                never = feed => never,
             }
-            None => Err(BuildResult::BuildSucceeded),
-        }
-        // } else {
-        //     Err(BuildResult::BuildFailed)
-        // }
-    }
-    async fn handle_running_state<S>(&mut self, wait: S, state: &BuildRunningState)
-    where
-        S: Stream<Item = Result<ContainerWaitResponse, Error>>,
-    {
-        wait.for_each(move |s| match s {
-            Ok(res) => {
-                let exit = ContainerExitCode(res.status_code);
-                let result: StepResult = exit.into();
-                self.state = BuildState::BuildReady;
-                self.completed_steps.push((state.step.to_owned(), result));
-                future::ready(())
-            }
-
-            Err(Error::DockerContainerWaitError { code, .. }) => {
-                self.fail_through = true;
-                let exit = ContainerExitCode(code);
-                let result: StepResult = exit.into();
-                self.state = BuildState::BuildReady;
-                self.completed_steps.push((state.step.to_owned(), result));
-                future::ready(())
-            }
-            Err(error) => {
-                self.state = BuildState::BuildFinished(BuildResult::BuildFailed);
-                println!("{:?}", error);
-                future::ready(())
+        });
+        self.watchers.insert(step.clone(), Watcher { logs, exit });
+    }
+
+    /// Kill a running step's container and stop following it, returning what
+    /// the step logged and, when the container had already stopped on its own
+    /// before the kill reached it, how it exited.
+    async fn kill_step(
+        &mut self,
+        conn: &impl ContainerService,
+        step: &StepName,
+        running: &RunningStep,
+    ) -> (Vec<LogOutput>, Option<ContainerExitCode>) {
+        let kill = conn
+            .kill_container(&running.container, None::<KillContainerOptions<String>>)
+            .await;
+        let exited = matches!(
+            kill,
+            Err(Error::DockerResponseServerError {
+                status_code: 409,
+                ..
+            })
+        );
+        match &kill {
+            Err(err) if !exited => self.emit_error(Some(step), err),
+            _ => {}
+        }
+        let Some(mut watcher) = self.watchers.remove(step) else {
+            return (vec![], None);
+        };
+        if kill.is_err() && !exited {
+            watcher.exit.abort();
+            return (watcher.logs(), None);
+        }
+        // The watcher ends with the container, after its last output.
+        let exit = match (&mut watcher.exit).await {
+            Ok(Some(Ok(res))) => Some(ContainerExitCode(res.status_code)),
+            Ok(Some(Err(Error::DockerContainerWaitError { code, .. }))) => {
+                Some(ContainerExitCode(code))
+            }
+            _ => None,
+        };
+        (watcher.logs(), exit.filter(|_| exited))
+    }
+
+    /// Refuse to start an invalid pipeline or one whose secrets are missing.
+    fn check_pipeline(&self) -> Result<(), String> {
+        self.pipeline.validate().map_err(|errs| errs.to_string())?;
+        let missing: Vec<String> = self
+            .pipeline
+            .secret_names()
+            .into_iter()
+            .filter(|name| self.secrets.get(name).is_none())
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Missing secrets: {}", missing.join(", ")))
+        }
+    }
+
+    /// Environment of a step's container: pipeline variables, overridden by the
+    /// step's own, then the secrets either of them asks for.
+    fn step_env(&self, step: &Step) -> Vec<String> {
+        let mut env = self.pipeline.env.clone();
+        env.extend(step.env.clone());
+        for name in self.pipeline.secrets.iter().chain(&step.secrets) {
+            if let Some(value) = self.secrets.get(name) {
+                env.insert(name.clone(), value.to_string());
+            }
+        }
+        let mut env: Vec<String> = env
+            .into_iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect();
+        env.sort();
+        env
+    }
+
+    /// Name of the volume steps share their files through.
+    pub fn workspace_name(&self) -> String {
+        format!("{}-{}-workspace", RUNNER_LABEL, self.id.0)
+    }
+
+    /// Create the volume steps share their files through.
+    async fn create_workspace(&mut self, conn: &impl ContainerService) -> Result<(), Error> {
+        let mut labels = HashMap::new();
+        labels.insert(RUNNER_LABEL.to_string(), "".to_string());
+        labels.insert(RUNNER_ID_LABEL.to_string(), self.runner.0.clone());
+        labels.insert(BUILD_LABEL.to_string(), self.id.0.clone());
+        let options = CreateVolumeOptions::new(
+            self.workspace_name(),
+            "local".to_string(),
+            HashMap::new(),
+            labels,
+        );
+        let volume = conn.create_volume(options).await?;
+        self.workspace = Some(volume.name);
+        Ok(())
+    }
+
+    /// Remove a container the build no longer needs, leaving it to
+    /// [`Build::cleanup`] if that fails.
+    async fn remove_container(&mut self, conn: &impl ContainerService, container: &str) {
+        let options = RemoveContainerOptions::new(true, true, false);
+        match conn.remove_container(container, Some(options)).await {
+            Ok(()) => self.containers.retain(|c| c != container),
+            Err(err) => self.emit_error(None, err),
+        }
+    }
+
+    /// Force-remove every container the build created, whether its step
+    /// finished or is still running, then the workspace they shared.
+    async fn cleanup(&mut self, conn: &impl ContainerService) {
+        for container in std::mem::take(&mut self.containers) {
+            let options = RemoveContainerOptions::new(true, true, false);
+            if let Err(err) = conn.remove_container(&container, Some(options)).await {
+                self.emit_error(None, err);
+            }
+        }
+        if let Some(volume) = self.workspace.take() {
+            let options = RemoveVolumeOptions::new(true);
+            if let Err(err) = conn.remove_volume(&volume, Some(options)).await {
+                self.emit_error(None, err);
+            }
+        }
+    }
+
+    /// Docker container names only allow `[a-zA-Z0-9][a-zA-Z0-9_.-]`. Steps
+    /// like `a b` and `a/b` sanitize to the same name, so the step's position in
+    /// the pipeline keeps them apart.
+    fn container_name(&self, step: &StepName) -> String {
+        let index = self
+            .pipeline
+            .steps
+            .iter()
+            .position(|s| &s.name == step)
+            .unwrap_or_default();
+        let step: String = step
+            .0
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.' | '-' => c,
+                _ => '-',
+            })
+            .collect();
+        format!("{}-{}-{}-{}", RUNNER_LABEL, self.id.0, index + 1, step)
+    }
+
+    /// Start every step that is ready to run, up to `max_parallelism` steps in
+    /// total alongside the ones already `running` or being started. Each step
+    /// is started from a task of its own, so that a slow image pull holds up
+    /// no other step.
+    fn start_steps(&mut self, conn: &impl ContainerService, mut running: BuildRunningState) {
+        match self.next_steps(&running) {
+            Ok(steps) => {
+                for step in steps {
+                    running.retrying.remove(&step.name);
+                    let starter = self.start_step(conn, &step);
+                    self.starting.insert(step.name, starter);
+                }
+                self.state = BuildState::BuildRunning(running)
+            }
+            Err(res) => self.state = BuildState::BuildFinished(res),
+        }
+    }
+
+    /// Pull the step's image, then create and start its container from a task
+    /// of its own. Only the pull is interrupted once the build stops: a
+    /// container whose creation was sent must make it into `containers`, or
+    /// the cleanup would miss it, though it is no longer started.
+    fn start_step(&self, conn: &impl ContainerService, step: &Step) -> JoinHandle<Started> {
+        let shell = step.shell.as_deref().unwrap_or(script::DEFAULT_SHELL);
+        let mut labels = HashMap::new();
+        labels.insert(RUNNER_LABEL.to_string(), "".to_string());
+        labels.insert(RUNNER_ID_LABEL.to_string(), self.runner.0.clone());
+        labels.insert(BUILD_LABEL.to_string(), self.id.0.clone());
+        labels.insert(STEP_LABEL.to_string(), step.name.0.clone());
+        // No TTY: it would merge stderr, where the script reports its failed
+        // command, into stdout, and echo piped input back into the output.
+        let mut config = CreateContainerConfig::new(
+            step.image.clone().into(),
+            false,
+            labels,
+            vec![shell.to_string(), "-c".to_string()],
+            script::render(&step.commands),
+        );
+        config.working_dir = Some(WORKSPACE_DIR.to_string());
+        if step.stdin.is_some() {
+            config.open_stdin = true;
+            config.attach_stdin = true;
+            config.stdin_once = true;
+        }
+        config.env = Some(self.step_env(step));
+        config.host_config = self.workspace.clone().map(|volume| HostConfig {
+            mounts: Some(vec![Mount {
+                target: Some(WORKSPACE_DIR.to_string()),
+                source: Some(volume),
+                typ: Some(MountTypeEnum::VOLUME),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        });
+        let options = CreateContainerOptions::new(self.container_name(&step.name), None);
+        let (conn, step) = (conn.clone(), step.clone());
+        let (events, stopping) = (self.events.clone(), self.stopping.clone());
+        tokio::spawn(async move {
+            tokio::select! {
+                pulled = Build::pull_image(&conn, &events, &step) => {
+                    if let Err(err) = pulled {
+                        return Started::Failed(None, err.to_string());
+                    }
+                }
+                _ = stopping.cancelled() => return Started::Interrupted(None),
+            }
+            let container = match conn.create_container(Some(options), config).await {
+                Ok(container) => container.id,
+                Err(err) => return Started::Failed(None, err.to_string()),
+            };
+            if stopping.is_cancelled() {
+                return Started::Interrupted(Some(container));
+            }
+            let input = match &step.stdin {
+                Some(stdin) => {
+                    // Attached before the start, so the script cannot read an
+                    // empty `stdin` first.
+                    let options = AttachContainerOptions::new(true, false, false, true, false);
+                    match conn.attach_container(&container, Some(options)).await {
+                        Ok(attached) => Some((stdin.clone(), attached)),
+                        Err(err) => return Started::Failed(Some(container), err.to_string()),
+                    }
+                }
+                None => None,
+            };
+            let options = None::<StartContainerOptions<String>>;
+            match conn.start_container(&container, options).await {
+                Ok(()) => Started::Running(container, input),
+                Err(err) => Started::Failed(Some(container), err.to_string()),
             }
         })
-        .await
+    }
+
+    /// Follow a step whose container started, or fail it if it could not be.
+    /// A step interrupted as the build stops is left to be skipped.
+    fn handle_started(
+        &mut self,
+        conn: &impl ContainerService,
+        state: &mut BuildRunningState,
+        step: StepName,
+        started: Started,
+    ) {
+        match started {
+            Started::Running(container, input) => {
+                self.containers.push(container.clone());
+                let running = RunningStep {
+                    container,
+                    started: Instant::now(),
+                    started_at: SystemTime::now(),
+                };
+                self.emit(BuildEvent::StepStarted {
+                    step: step.clone(),
+                    attempt: self.attempts.get(&step).map_or(0, Vec::len) + 1,
+                    at: running.started_at,
+                });
+                self.watch_step(conn, &step, &running.container, input);
+                state.steps.insert(step, running);
+            }
+            Started::Interrupted(container) => self.containers.extend(container),
+            Started::Failed(container, err) => {
+                self.containers.extend(container);
+                self.emit_error(Some(&step), err);
+                let result = StepResult::StepFailed(ContainerExitCode(UNKNOWN_EXIT_CODE));
+                let mut completed = CompletedStep::new(step.clone(), result);
+                completed.attempts = self.attempts.remove(&step).unwrap_or_default();
+                self.finish_step(completed);
+            }
+        }
+    }
+
+    /// Interrupt the steps being started, now that the build stops, and wait
+    /// for them: those that started anyway are followed like the running
+    /// ones, so that they can be killed.
+    async fn stop_starting(&mut self, conn: &impl ContainerService, state: &mut BuildRunningState) {
+        self.stopping.cancel();
+        for (step, starter) in std::mem::take(&mut self.starting) {
+            let started = starter
+                .await
+                .unwrap_or_else(|err| Started::Failed(None, err.to_string()));
+            self.handle_started(conn, state, step, started);
+        }
+    }
+
+    /// Make the step's image available locally according to its pull policy,
+    /// sending the progress of a pull as [`BuildEvent::StepPull`].
+    async fn pull_image(
+        conn: &impl ContainerService,
+        events: &broadcast::Sender<BuildEvent>,
+        step: &Step,
+    ) -> Result<(), Error> {
+        match step.pull_policy {
+            PullPolicy::Never => return Ok(()),
+            PullPolicy::IfNotPresent => match conn.inspect_image(&step.image.0).await {
+                Ok(_) => return Ok(()),
+                Err(Error::DockerResponseServerError {
+                    status_code: 404, ..
+                }) => {}
+                Err(err) => return Err(err),
+            },
+            PullPolicy::Always => {}
+        }
+        let (repository, tag) = step.image.reference();
+        let mut pull =
+            Box::pin(conn.create_image(Some(CreateImageOptions::new(repository, tag, None))));
+        while let Some(info) = pull.next().await {
+            let info = info?;
+            // Skip the per-layer progress bars, keep the status changes.
+            if let (Some(status), None) = (info.status, info.progress) {
+                let _ = events.send(BuildEvent::StepPull {
+                    step: step.name.clone(),
+                    image: step.image.0.clone(),
+                    layer: info.id,
+                    status,
+                    at: SystemTime::now(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Pick the steps whose dependencies have all succeeded, in declaration
+    /// order, leaving room for the `running` ones and those being started under
+    /// `max_parallelism`.
+    /// Steps that can no longer run are recorded as skipped first; once nothing
+    /// is left to run the overall build result is returned instead.
+    pub fn next_steps(&mut self, running: &BuildRunningState) -> Result<Vec<Step>, BuildResult> {
+        self.skip_unreachable_steps();
+        let graph = StepGraph::new(&self.pipeline);
+        let now = Instant::now();
+        let pending: Vec<Step> = self
+            .pending_steps()
+            .into_iter()
+            .filter(|step| !running.steps.contains_key(&step.name))
+            .filter(|step| !self.starting.contains_key(&step.name))
+            .collect();
+        // Steps waiting out their retry backoff will still run.
+        let busy = !running.steps.is_empty()
+            || !self.starting.is_empty()
+            || running.retrying.values().any(|at| *at > now);
+        if pending.is_empty() && !busy {
+            return Err(if self.all_steps_succeeded() {
+                BuildResult::BuildSucceeded
+            } else {
+                BuildResult::BuildFailed
+            });
+        }
+        let slots = self
+            .max_parallelism
+            .max(1)
+            .saturating_sub(running.steps.len() + self.starting.len());
+        let ready: Vec<Step> = pending
+            .iter()
+            .filter(|step| graph.readiness(&step.name, &self.completed_steps) == Readiness::Ready)
+            .filter(|step| running.retrying.get(&step.name).is_none_or(|at| *at <= now))
+            .take(slots)
+            .cloned()
+            .collect();
+        if ready.is_empty() && !busy {
+            // Nothing is running and nothing can start: the remaining steps wait
+            // on each other or on steps that are not part of the pipeline.
+            self.abandon_pending_steps();
+            return Err(BuildResult::BuildFailed);
+        }
+        Ok(ready)
+    }
+
+    async fn handle_running_state(
+        &mut self,
+        conn: &impl ContainerService,
+        state: &mut BuildRunningState,
+        step: StepName,
+        running: &RunningStep,
+        logs: Vec<LogOutput>,
+        res: Option<Result<ContainerWaitResponse, Error>>,
+    ) {
+        let exit = match res {
+            Some(Ok(res)) => ContainerExitCode(res.status_code),
+            Some(Err(Error::DockerContainerWaitError { code, .. })) => ContainerExitCode(code),
+            res => {
+                match res {
+                    Some(Err(err)) => self.emit_error(Some(&step), err),
+                    _ => self.emit_error(Some(&step), "container wait ended without a response"),
+                }
+                // Lost track of the step: fail the build with it, and leave
+                // its container to the cleanup.
+                self.state = BuildState::BuildFinished(BuildResult::BuildFailed);
+                let result = StepResult::StepFailed(ContainerExitCode(UNKNOWN_EXIT_CODE));
+                self.complete_step(conn, state, step, running, result, logs)
+                    .await;
+                return self.fail(conn, state).await;
+            }
+        };
+        self.complete_step(conn, state, step, running, exit.into(), logs)
+            .await
+    }
+
+    /// Record the result of a step whose container has stopped, along with
+    /// everything it logged. A failed step its retry policy allows to run again
+    /// is scheduled for `state.retrying` instead of completed.
+    async fn complete_step(
+        &mut self,
+        conn: &impl ContainerService,
+        state: &mut BuildRunningState,
+        step: StepName,
+        running: &RunningStep,
+        result: StepResult,
+        logs: Vec<LogOutput>,
+    ) {
+        let finished = SystemTime::now();
+        let failed_command = match result {
+            StepResult::StepSucceeded => None,
+            _ => script::failed_command(&logs),
+        };
+        let mut attempt = StepAttempt::new(
+            result.clone(),
+            running.started_at,
+            finished,
+            logs,
+            failed_command,
+        );
+        // The exit code alone does not tell an out of memory kill apart.
+        let options = InspectContainerOptions::new(false);
+        match conn
+            .inspect_container(&running.container, Some(options))
+            .await
+        {
+            Ok(inspect) => {
+                attempt.oom_killed = inspect
+                    .state
+                    .and_then(|state| state.oom_killed)
+                    .unwrap_or_default()
+            }
+            Err(err) => self.emit_error(Some(&step), err),
+        }
+        let mut attempts = self.attempts.remove(&step).unwrap_or_default();
+        attempts.push(attempt);
+        let retry = self
+            .pipeline
+            .steps
+            .iter()
+            .find(|s| s.name == step)
+            .and_then(|s| s.retry.as_ref())
+            // Nothing runs again once the build has failed.
+            .filter(|_| !matches!(self.state, BuildState::BuildFinished(_)));
+        if let (StepResult::StepFailed(exit), Some(retry)) = (&result, retry) {
+            if let Some(delay) = retry.next_delay(exit, attempts.len()) {
+                self.emit(BuildEvent::StepRetrying {
+                    step: step.clone(),
+                    attempt: attempts.len(),
+                    exit_code: exit.clone(),
+                    delay,
+                    at: finished,
+                });
+                state.retrying.insert(step.clone(), Instant::now() + delay);
+                self.attempts.insert(step, attempts);
+                // The next attempt reuses the container name.
+                self.remove_container(conn, &running.container).await;
+                return;
+            }
+        }
+        let mut completed = CompletedStep::new(step, result);
+        completed.attempts = attempts;
+        self.finish_step(completed);
+    }
+
+    /// Give up on every step that has not completed: those that already ran
+    /// keep the result of their last attempt, the others are skipped.
+    fn abandon_pending_steps(&mut self) {
+        for step in self.pending_steps() {
+            let attempts = self.attempts.remove(&step.name).unwrap_or_default();
+            let result = attempts
+                .last()
+                .map_or(StepResult::StepSkipped, |a| a.result.clone());
+            let mut completed = CompletedStep::new(step.name, result);
+            completed.attempts = attempts;
+            self.finish_step(completed);
+        }
+    }
+
+    /// The earliest moment a running step or the whole build times out, or a
+    /// failed step is due to be retried.
+    fn next_deadline(&self, running: &BuildRunningState) -> Option<Instant> {
+        let now = Instant::now();
+        running
+            .steps
+            .iter()
+            .filter_map(|(name, running)| self.step_deadline(name, running))
+            .chain(running.retrying.values().copied().filter(|at| *at > now))
+            .chain(self.build_deadline())
+            .min()
+    }
+
+    fn build_deadline(&self) -> Option<Instant> {
+        Some(self.started? + self.pipeline.timeout?)
+    }
+
+    fn step_deadline(&self, name: &StepName, running: &RunningStep) -> Option<Instant> {
+        let step = self.pipeline.steps.iter().find(|s| &s.name == name)?;
+        Some(running.started + step.timeout?)
+    }
+
+    /// Kill the steps that ran out of time. Once the build itself is out of time
+    /// every running step is killed and the steps left are skipped.
+    async fn handle_timeouts(
+        &mut self,
+        conn: &impl ContainerService,
+        state: &mut BuildRunningState,
+    ) {
+        let now = Instant::now();
+        let build_timed_out = self.build_deadline().is_some_and(|d| d <= now);
+        if build_timed_out {
+            self.emit(BuildEvent::BuildTimedOut {
+                at: SystemTime::now(),
+            });
+            self.stop_starting(conn, state).await;
+        }
+        let expired: Vec<StepName> = state
+            .steps
+            .iter()
+            .filter(|(name, running)| {
+                build_timed_out || self.step_deadline(name, running).is_some_and(|d| d <= now)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        for step in expired {
+            if let Some(running) = state.steps.remove(&step) {
+                let (logs, exit) = self.kill_step(conn, &step, &running).await;
+                let result = exit.map_or(StepResult::StepTimedOut, StepResult::from);
+                self.complete_step(conn, state, step, &running, result, logs)
+                    .await;
+            }
+        }
+        if build_timed_out {
+            self.abandon_pending_steps();
+            self.state = BuildState::BuildFinished(BuildResult::BuildFailed);
+        }
+    }
+
+    /// Kill every running step and skip the ones left, ending the build as
+    /// cancelled. Steps that already ran keep their attempts and are recorded
+    /// as cancelled rather than skipped.
+    async fn cancel(&mut self, conn: &impl ContainerService, state: &mut BuildRunningState) {
+        self.stop_starting(conn, state).await;
+        for (step, running) in std::mem::take(&mut state.steps) {
+            let (logs, exit) = self.kill_step(conn, &step, &running).await;
+            let result = exit.map_or(StepResult::StepCancelled, StepResult::from);
+            self.complete_step(conn, state, step, &running, result, logs)
+                .await;
+        }
+        for step in self.pending_steps() {
+            let attempts = self.attempts.remove(&step.name).unwrap_or_default();
+            let result = match attempts.is_empty() {
+                true => StepResult::StepSkipped,
+                false => StepResult::StepCancelled,
+            };
+            let mut completed = CompletedStep::new(step.name.clone(), result);
+            completed.attempts = attempts;
+            self.finish_step(completed);
+        }
+        self.state = BuildState::BuildFinished(BuildResult::BuildCancelled);
+    }
+
+    /// Give up on the build once it lost track of a step's container: the
+    /// running steps are killed and recorded as failed, and the steps left are
+    /// abandoned.
+    async fn fail(&mut self, conn: &impl ContainerService, state: &mut BuildRunningState) {
+        self.state = BuildState::BuildFinished(BuildResult::BuildFailed);
+        self.stop_starting(conn, state).await;
+        let lost = StepResult::StepFailed(ContainerExitCode(UNKNOWN_EXIT_CODE));
+        for (step, running) in std::mem::take(&mut state.steps) {
+            let (logs, exit) = self.kill_step(conn, &step, &running).await;
+            let result = exit.map_or_else(|| lost.clone(), StepResult::from);
+            self.complete_step(conn, state, step, &running, result, logs)
+                .await;
+        }
+        self.abandon_pending_steps();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use http::{Method, StatusCode};
+    use hyper::body::Bytes;
+    use nonempty::{nonempty, NonEmpty};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        core::{
+            runtime::fake::{FakeContainers, FakeFault, FakeRun},
+            secrets,
+            testing::step,
+            RetryPolicy,
+        },
+        docker::mock::{frame, MockDocker, MockResponse},
+    };
+
+    fn build(steps: Vec<Step>) -> Build {
+        let pipeline = Pipeline::new(NonEmpty::from_vec(steps).unwrap());
+        Build::new(pipeline, BuildState::BuildReady, vec![])
+    }
+
+    fn result(steps: &CompletedSteps, name: &str) -> StepResult {
+        let step = steps.iter().find(|s| s.name.0 == name).unwrap();
+        step.result.clone()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn runs_steps_in_dependency_order() {
+        let conn = FakeContainers::default();
+        let mut b = build(vec![step("c", &["b"]), step("b", &["a"]), step("a", &[])]);
+        let (res, steps) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildSucceeded);
+        assert_eq!(conn.started(), vec!["a", "b", "c"]);
+        assert!(steps.iter().all(|s| s.result == StepResult::StepSucceeded));
+        assert_eq!(conn.leftovers(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_step_skips_its_dependents() {
+        let conn = FakeContainers::default().script("a", vec![FakeRun::exit(2)]);
+        let mut b = build(vec![step("a", &[]), step("b", &["a"]), step("c", &[])]);
+        let (res, steps) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildFailed);
+        assert_eq!(
+            result(&steps, "a"),
+            StepResult::StepFailed(ContainerExitCode(2))
+        );
+        assert_eq!(result(&steps, "b"), StepResult::StepSkipped);
+        assert_eq!(result(&steps, "c"), StepResult::StepSucceeded);
+        assert_eq!(conn.leftovers(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn runs_at_most_max_parallelism_steps() {
+        let names = ["a", "b", "c", "d", "e"];
+        let mut conn = FakeContainers::default();
+        for name in names {
+            let run = FakeRun::exit(0).lasting(Duration::from_secs(1));
+            conn = conn.script(name, vec![run]);
+        }
+        let steps = names.iter().map(|name| step(name, &[])).collect();
+        let mut b = build(steps).with_max_parallelism(2);
+        let (res, _) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildSucceeded);
+        assert_eq!(conn.started().len(), 5);
+        assert_eq!(conn.max_running(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_pulls_hold_up_no_other_step() {
+        let conn = FakeContainers::default()
+            .image("alpine:3")
+            .script("a", vec![FakeRun::exit(0).lasting(Duration::from_secs(1))])
+            .pull_lasting(Duration::from_secs(60));
+        let mut b = step("b", &[]);
+        b.pull_policy = PullPolicy::Always;
+        let mut b = build(vec![step("a", &[]), b, step("c", &["a"])]);
+        let mut events = b.subscribe();
+        let started = Instant::now();
+        let ((res, _), finished) = tokio::join!(b.run(&conn), async {
+            let mut finished = vec![];
+            while let Ok(event) = events.recv().await {
+                match event {
+                    BuildEvent::StepFinished { step, .. } => {
+                        finished.push((step.0, started.elapsed()))
+                    }
+                    BuildEvent::BuildFinished { .. } => break,
+                    _ => {}
+                }
+            }
+            finished
+        });
+        assert_eq!(res, BuildResult::BuildSucceeded);
+        // `a` finished and `c` ran after it while `b`'s image was pulled.
+        assert_eq!(conn.started(), vec!["a", "c", "b"]);
+        assert_eq!(
+            finished,
+            vec![
+                ("a".to_string(), Duration::from_secs(1)),
+                ("c".to_string(), Duration::from_secs(1)),
+                ("b".to_string(), Duration::from_secs(60)),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_logs_and_failed_command() {
+        let commands = nonempty!["true".to_string(), "false".to_string()];
+        let run = FakeRun::shell(&script::render(&commands));
+        let conn = FakeContainers::default().script("a", vec![run.clone()]);
+        let mut a = step("a", &[]);
+        a.commands = commands;
+        let mut b = build(vec![a]);
+        let (_, steps) = b.run(&conn).await;
+        assert_eq!(steps[0].attempts.len(), 1);
+        assert_eq!(steps[0].attempts[0].logs, run.output);
+        assert_eq!(steps[0].attempts[0].failed_command, Some(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn records_steps_killed_for_running_out_of_memory() {
+        let conn = FakeContainers::default()
+            .script("a", vec![FakeRun::exit(0).out_of_memory()])
+            .script("b", vec![FakeRun::exit(137)]);
+        let mut b = build(vec![step("a", &[]), step("b", &[])]);
+        b.run(&conn).await;
+        let oom_killed: Vec<_> = b
+            .summary()
+            .unwrap()
+            .steps
+            .iter()
+            .map(|s| (s.name.0.clone(), s.oom_killed))
+            .collect();
+        assert_eq!(
+            oom_killed,
+            vec![("a".to_string(), true), ("b".to_string(), false)]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_failed_steps() {
+        let runs = vec![FakeRun::exit(1), FakeRun::exit(1), FakeRun::exit(0)];
+        let conn = FakeContainers::default().script("a", runs);
+        let mut a = step("a", &[]);
+        a.retry = Some(RetryPolicy {
+            max_attempts: 3,
+            backoff: Duration::from_secs(5),
+            exit_codes: vec![],
+        });
+        let mut b = build(vec![a, step("b", &["a"])]);
+        b.id = BuildId::from("x");
+        let started = Instant::now();
+        let (res, steps) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildSucceeded);
+        assert_eq!(conn.started(), vec!["a", "a", "a", "b"]);
+        // Each attempt's container is gone before the next one takes its name.
+        assert_eq!(
+            conn.created(),
+            vec!["nova-x-1-a", "nova-x-1-a", "nova-x-1-a", "nova-x-2-b"]
+        );
+        assert_eq!(conn.leftovers(), 0);
+        let attempts: Vec<_> = steps[0].attempts.iter().map(|a| &a.result).collect();
+        assert_eq!(
+            attempts,
+            vec![
+                &StepResult::StepFailed(ContainerExitCode(1)),
+                &StepResult::StepFailed(ContainerExitCode(1)),
+                &StepResult::StepSucceeded,
+            ]
+        );
+        // 5s before the first retry, doubled before the second.
+        assert!(started.elapsed() >= Duration::from_secs(15));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn only_retries_listed_exit_codes() {
+        let conn = FakeContainers::default().script("a", vec![FakeRun::exit(1)]);
+        let mut a = step("a", &[]);
+        a.retry = Some(RetryPolicy {
+            max_attempts: 3,
+            backoff: Duration::ZERO,
+            exit_codes: vec![75],
+        });
+        let mut b = build(vec![a]);
+        let (res, steps) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildFailed);
+        assert_eq!(steps[0].attempts.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn kills_steps_that_time_out() {
+        let run = FakeRun::exit(0).lasting(Duration::from_secs(60));
+        let conn = FakeContainers::default().script("a", vec![run]);
+        let mut a = step("a", &[]);
+        a.timeout = Some(Duration::from_secs(5));
+        let mut b = build(vec![a, step("b", &[])]);
+        let (res, steps) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildFailed);
+        assert_eq!(result(&steps, "a"), StepResult::StepTimedOut);
+        assert_eq!(result(&steps, "b"), StepResult::StepSucceeded);
+        assert_eq!(conn.killed(), vec!["a"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn build_timeout_skips_the_steps_left() {
+        let run = FakeRun::exit(0).lasting(Duration::from_secs(60));
+        let conn = FakeContainers::default().script("a", vec![run]);
+        let mut b = build(vec![step("a", &[]), step("b", &["a"])]);
+        b.pipeline.timeout = Some(Duration::from_secs(5));
+        let (res, steps) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildFailed);
+        assert_eq!(result(&steps, "a"), StepResult::StepTimedOut);
+        assert_eq!(result(&steps, "b"), StepResult::StepSkipped);
+        assert_eq!(conn.leftovers(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn build_timeout_interrupts_image_pulls() {
+        let conn = FakeContainers::default().pull_lasting(Duration::from_secs(3600));
+        let mut b = build(vec![step("a", &[]), step("b", &["a"])]);
+        b.pipeline.timeout = Some(Duration::from_secs(5));
+        let started = Instant::now();
+        let (res, steps) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildFailed);
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+        for name in ["a", "b"] {
+            assert_eq!(result(&steps, name), StepResult::StepSkipped);
+        }
+        assert!(conn.created().is_empty());
+        assert_eq!(conn.leftovers(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn steps_time_out_while_other_images_are_pulled() {
+        let conn = FakeContainers::default()
+            .image("alpine:3")
+            .script("a", vec![FakeRun::exit(0).lasting(Duration::from_secs(60))])
+            .pull_lasting(Duration::from_secs(60));
+        let mut a = step("a", &[]);
+        a.timeout = Some(Duration::from_secs(2));
+        let mut b = step("b", &[]);
+        b.pull_policy = PullPolicy::Always;
+        let mut b = build(vec![a, b]);
+        let mut events = b.subscribe();
+        let started = Instant::now();
+        let ((res, steps), timed_out) = tokio::join!(b.run(&conn), async {
+            while let Ok(event) = events.recv().await {
+                if let BuildEvent::StepFinished { step, .. } = event {
+                    if step.0 == "a" {
+                        return Some(started.elapsed());
+                    }
+                }
+            }
+            None
+        });
+        assert_eq!(res, BuildResult::BuildFailed);
+        assert_eq!(result(&steps, "a"), StepResult::StepTimedOut);
+        assert_eq!(timed_out, Some(Duration::from_secs(2)));
+        assert_eq!(result(&steps, "b"), StepResult::StepSucceeded);
+        assert_eq!(conn.killed(), vec!["a"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn steps_exiting_as_they_time_out_keep_their_exit_code() {
+        let conn = FakeContainers::default().script("a", vec![FakeRun::exit(3)]);
+        let mut b = build(vec![step("a", &[])]);
+        let mut events = b.subscribe();
+        b.progress(&conn).await;
+        b.progress(&conn).await;
+        // The container exits, then the step runs out of time before the
+        // build gets to its exit: the kill finds it no longer running.
+        time::sleep(Duration::from_secs(1)).await;
+        b.pipeline.steps.head.timeout = Some(Duration::ZERO);
+        let BuildState::BuildRunning(mut state) = b.state.clone() else {
+            panic!("a should be running: {:?}", b.state);
+        };
+        b.handle_timeouts(&conn, &mut state).await;
+        assert_eq!(
+            result(&b.completed_steps, "a"),
+            StepResult::StepFailed(ContainerExitCode(3))
+        );
+        assert!(conn.killed().is_empty());
+        while let Ok(event) = events.try_recv() {
+            assert!(
+                !matches!(event, BuildEvent::DockerError { .. }),
+                "{event:?}"
+            );
+        }
+    }
+
+    /// Results of the `StepFinished` events in `events`, by step.
+    fn finished(events: &mut broadcast::Receiver<BuildEvent>) -> Vec<(String, StepResult)> {
+        let mut finished = vec![];
+        while let Ok(event) = events.try_recv() {
+            if let BuildEvent::StepFinished { step, result, .. } = event {
+                finished.push((step.0, result));
+            }
+        }
+        finished.sort_by(|a, b| a.0.cmp(&b.0));
+        finished
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failing_to_start_a_step_only_fails_its_branch() {
+        let lost = StepResult::StepFailed(ContainerExitCode(UNKNOWN_EXIT_CODE));
+        let conn = FakeContainers::default()
+            .script("a", vec![FakeRun::exit(0).lasting(Duration::from_secs(60))])
+            .script("b", vec![FakeRun::exit(0).failing(FakeFault::Start)]);
+        let mut b = build(vec![
+            step("a", &[]),
+            step("b", &[]),
+            step("c", &["b"]),
+            step("d", &["a"]),
+        ]);
+        let mut events = b.subscribe();
+        let (res, steps) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildFailed);
+        assert_eq!(
+            finished(&mut events),
+            vec![
+                ("a".to_string(), StepResult::StepSucceeded),
+                ("b".to_string(), lost.clone()),
+                ("c".to_string(), StepResult::StepSkipped),
+                ("d".to_string(), StepResult::StepSucceeded),
+            ]
+        );
+        assert_eq!(result(&steps, "b"), lost);
+        assert_eq!(b.summary().unwrap().steps.len(), 4);
+        assert!(conn.killed().is_empty());
+        assert_eq!(conn.leftovers(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failing_to_pull_an_image_only_fails_its_branch() {
+        let conn = FakeContainers::default()
+            .unpullable("private/app:1")
+            .script("a", vec![FakeRun::exit(0).lasting(Duration::from_secs(10))]);
+        let mut pulled = step("b", &[]);
+        pulled.image = "private/app:1".into();
+        let mut b = build(vec![
+            step("a", &[]),
+            pulled,
+            step("c", &["b"]),
+            step("d", &["a"]),
+        ]);
+        let mut events = b.subscribe();
+        let (res, _) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildFailed);
+        assert_eq!(
+            finished(&mut events),
+            vec![
+                ("a".to_string(), StepResult::StepSucceeded),
+                (
+                    "b".to_string(),
+                    StepResult::StepFailed(ContainerExitCode(UNKNOWN_EXIT_CODE))
+                ),
+                ("c".to_string(), StepResult::StepSkipped),
+                ("d".to_string(), StepResult::StepSucceeded),
+            ]
+        );
+        assert_eq!(conn.started(), vec!["a", "d"]);
+        assert_eq!(conn.leftovers(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failing_to_start_the_last_step_ends_the_build() {
+        let conn =
+            FakeContainers::default().script("a", vec![FakeRun::exit(0).failing(FakeFault::Start)]);
+        let mut b = build(vec![step("a", &[]), step("b", &["a"])]);
+        let (res, steps) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildFailed);
+        assert_eq!(result(&steps, "b"), StepResult::StepSkipped);
+        assert_eq!(conn.leftovers(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failing_to_wait_on_a_step_completes_every_step() {
+        let lost = StepResult::StepFailed(ContainerExitCode(UNKNOWN_EXIT_CODE));
+        let mut retried = step("r", &[]);
+        retried.retry = Some(RetryPolicy {
+            max_attempts: 2,
+            backoff: Duration::from_secs(60),
+            exit_codes: vec![],
+        });
+        let conn = FakeContainers::default()
+            .script("a", vec![FakeRun::exit(0).lasting(Duration::from_secs(60))])
+            .script(
+                "b",
+                vec![FakeRun::exit(0)
+                    .failing(FakeFault::Wait)
+                    .lasting(Duration::from_secs(5))],
+            )
+            .script("r", vec![FakeRun::exit(3)]);
+        let mut b = build(vec![
+            step("a", &[]),
+            step("b", &[]),
+            step("c", &["b"]),
+            retried,
+        ]);
+        let mut events = b.subscribe();
+        let (res, steps) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildFailed);
+        assert_eq!(
+            finished(&mut events),
+            vec![
+                ("a".to_string(), lost.clone()),
+                ("b".to_string(), lost.clone()),
+                ("c".to_string(), StepResult::StepSkipped),
+                (
+                    "r".to_string(),
+                    StepResult::StepFailed(ContainerExitCode(3))
+                ),
+            ]
+        );
+        assert_eq!(
+            steps
+                .iter()
+                .find(|s| s.name.0 == "b")
+                .unwrap()
+                .attempts
+                .len(),
+            1
+        );
+        assert_eq!(b.summary().unwrap().steps.len(), 4);
+        // `b`'s container, lost track of, is only removed by the cleanup.
+        assert_eq!(conn.killed(), vec!["a"]);
+        assert_eq!(conn.leftovers(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sends_logs_while_steps_run() {
+        let minute = FakeRun::exit(0)
+            .stdout("hello")
+            .stderr("world")
+            .lasting(Duration::from_secs(60));
+        let conn = FakeContainers::default()
+            .script("a", vec![minute])
+            .script("b", vec![FakeRun::exit(0).lasting(Duration::from_secs(10))])
+            .script("c", vec![FakeRun::exit(0).lasting(Duration::from_secs(20))]);
+        let mut b = build(vec![step("a", &[]), step("b", &[]), step("c", &[])]);
+        let mut events = b.subscribe();
+        let started = Instant::now();
+        let mut lines = vec![];
+        let ((res, _), ()) = tokio::join!(b.run(&conn), async {
+            while let Ok(event) = events.recv().await {
+                match event {
+                    BuildEvent::StepLog { step, line, .. } => {
+                        lines.push((step.0, line.to_string(), started.elapsed()))
+                    }
+                    BuildEvent::BuildFinished { .. } => break,
+                    _ => {}
+                }
+            }
+        });
+        assert_eq!(res, BuildResult::BuildSucceeded);
+        // Sent once each as soon as written, though `b` and `c` finishing
+        // made the build follow `a` three times.
+        assert_eq!(
+            lines,
+            vec![
+                ("a".to_string(), "hello\n".to_string(), Duration::ZERO),
+                ("a".to_string(), "world\n".to_string(), Duration::ZERO),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn feeds_stdin_to_steps_that_ask_for_it() {
+        // Running long enough to read its input before it exits.
+        let run = FakeRun::exit(0).lasting(Duration::from_secs(1));
+        let conn = FakeContainers::default().script("a", vec![run]);
+        let mut a = step("a", &[]);
+        a.stdin = Some("y\n".to_string());
+        let mut b = build(vec![a, step("b", &[])]);
+        let (res, _) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildSucceeded);
+        assert_eq!(conn.stdin("a").as_deref(), Some("y\n"));
+        assert_eq!(conn.stdin("b"), None);
+        let a = conn.config("a").unwrap();
+        assert!(a.open_stdin && a.attach_stdin && a.stdin_once && !a.tty);
+        let b = conn.config("b").unwrap();
+        assert!(!b.open_stdin && !b.attach_stdin && !b.stdin_once && !b.tty);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn steps_that_never_read_stdin_still_time_out() {
+        let run = FakeRun::exit(0)
+            .ignoring_stdin()
+            .lasting(Duration::from_secs(60));
+        let conn = FakeContainers::default().script("a", vec![run]);
+        let mut a = step("a", &[]);
+        // Far more than the pipe holds.
+        a.stdin = Some("y\n".repeat(1 << 16));
+        a.timeout = Some(Duration::from_secs(5));
+        let b = step("b", &[]);
+        let mut b = build(vec![a, b]);
+        let started = Instant::now();
+        let (res, steps) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildFailed);
+        assert!(started.elapsed() < Duration::from_secs(60));
+        assert_eq!(result(&steps, "a"), StepResult::StepTimedOut);
+        assert_eq!(result(&steps, "b"), StepResult::StepSucceeded);
+        assert_eq!(conn.killed(), vec!["a"]);
+        assert_eq!(conn.leftovers(), 0);
+    }
+
+    #[tokio::test]
+    async fn feeds_stdin_through_docker() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::POST,
+            "/volumes/create",
+            MockResponse::json(
+                StatusCode::CREATED,
+                json!({
+                    "Name": "nova-x-workspace",
+                    "Driver": "local",
+                    "Mountpoint": "/var/lib/docker/volumes/nova-x-workspace/_data",
+                    "Labels": {},
+                    "Scope": "local",
+                    "Options": {},
+                }),
+            ),
+        )
+        .on(
+            Method::POST,
+            "/containers/create",
+            MockResponse::json(StatusCode::CREATED, json!({ "Id": "abc", "Warnings": [] })),
+        )
+        .on(
+            Method::POST,
+            "/containers/abc/attach",
+            MockResponse::upgrade([] as [Bytes; 0]),
+        )
+        .on(
+            Method::POST,
+            "/containers/abc/start",
+            MockResponse::empty(StatusCode::NO_CONTENT),
+        )
+        .on(
+            Method::POST,
+            "/containers/abc/wait",
+            MockResponse::json(StatusCode::OK, json!({ "StatusCode": 0 })),
+        )
+        .on(
+            Method::GET,
+            "/containers/abc/logs",
+            MockResponse::chunks(StatusCode::OK, [frame(1, "+ cat\n"), frame(1, "y\n")]),
+        )
+        .on(
+            Method::DELETE,
+            "/containers/abc",
+            MockResponse::empty(StatusCode::NO_CONTENT),
+        )
+        .on(
+            Method::DELETE,
+            "/volumes/nova-x-workspace",
+            MockResponse::empty(StatusCode::NO_CONTENT),
+        );
+        let mut a = step("a", &[]);
+        a.commands = nonempty!["cat".to_string()];
+        a.pull_policy = PullPolicy::Never;
+        a.stdin = Some("y\n".to_string());
+        let mut b = build(vec![a]);
+        b.id = BuildId::from("x");
+        let (res, steps) = b.run(&mock.docker()).await;
+        assert_eq!(res, BuildResult::BuildSucceeded);
+        assert_eq!(steps[0].attempts[0].logs.len(), 2);
+
+        let requests = mock.requests();
+        let paths: Vec<_> = requests.iter().map(|r| r.path.as_str()).collect();
+        let position = |path| paths.iter().position(|p| *p == path).unwrap();
+        assert!(position("/containers/abc/attach") < position("/containers/abc/start"));
+        let create = &requests[position("/containers/create")].json();
+        assert_eq!(
+            (
+                &create["OpenStdin"],
+                &create["AttachStdin"],
+                &create["StdinOnce"],
+                &create["Tty"]
+            ),
+            (&json!(true), &json!(true), &json!(true), &json!(false))
+        );
+        let attach = &requests[position("/containers/abc/attach")];
+        assert_eq!(
+            attach.query.as_deref(),
+            Some("stdin=true&stdout=false&stderr=false&stream=true&logs=false")
+        );
+        assert_eq!(mock.input(), "y\n");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancellation_kills_running_steps() {
+        let run = FakeRun::exit(0).lasting(Duration::from_secs(60));
+        let conn = FakeContainers::default().script("a", vec![run]);
+        let cancellation = CancellationToken::new();
+        let mut b =
+            build(vec![step("a", &[]), step("b", &["a"])]).with_cancellation(cancellation.clone());
+        tokio::spawn(async move {
+            time::sleep(Duration::from_secs(1)).await;
+            cancellation.cancel();
+        });
+        let (res, steps) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildCancelled);
+        assert_eq!(result(&steps, "a"), StepResult::StepCancelled);
+        assert_eq!(steps[0].attempts.len(), 1);
+        assert_eq!(result(&steps, "b"), StepResult::StepSkipped);
+        assert!(steps[1].attempts.is_empty());
+        assert_eq!(conn.killed(), vec!["a"]);
+        assert_eq!(conn.leftovers(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancellation_interrupts_image_pulls() {
+        let run = FakeRun::exit(0).lasting(Duration::from_secs(60));
+        let conn = FakeContainers::default()
+            .image("alpine:3")
+            .script("a", vec![run])
+            .pull_lasting(Duration::from_secs(3600));
+        let cancellation = CancellationToken::new();
+        let mut b = step("b", &[]);
+        b.pull_policy = PullPolicy::Always;
+        let mut b =
+            build(vec![step("a", &[]), b, step("c", &[])]).with_cancellation(cancellation.clone());
+        tokio::spawn(async move {
+            time::sleep(Duration::from_secs(1)).await;
+            cancellation.cancel();
+        });
+        let started = Instant::now();
+        let (res, steps) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildCancelled);
+        assert!(started.elapsed() < Duration::from_secs(60));
+        assert_eq!(steps.len(), 3);
+        assert_eq!(result(&steps, "a"), StepResult::StepCancelled);
+        assert_eq!(result(&steps, "b"), StepResult::StepSkipped);
+        // Started alongside `b`'s pull, and done before the cancellation.
+        assert_eq!(result(&steps, "c"), StepResult::StepSucceeded);
+        assert_eq!(conn.started(), vec!["a", "c"]);
+        assert_eq!(conn.killed(), vec!["a"]);
+        assert_eq!(conn.leftovers(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancellation_waits_for_containers_being_created() {
+        let conn = FakeContainers::default()
+            .image("alpine:3")
+            .create_lasting(Duration::from_secs(10));
+        let cancellation = CancellationToken::new();
+        let mut b =
+            build(vec![step("a", &[]), step("b", &[])]).with_cancellation(cancellation.clone());
+        tokio::spawn(async move {
+            time::sleep(Duration::from_secs(1)).await;
+            cancellation.cancel();
+        });
+        let (res, steps) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildCancelled);
+        for name in ["a", "b"] {
+            assert_eq!(result(&steps, name), StepResult::StepSkipped);
+        }
+        // Both containers were being created when the build was cancelled,
+        // and neither was started.
+        assert_eq!(conn.created().len(), 2);
+        assert!(conn.started().is_empty());
+        assert_eq!(conn.leftovers(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancellation_keeps_the_attempts_of_steps_waiting_to_retry() {
+        let conn = FakeContainers::default().script("a", vec![FakeRun::exit(1)]);
+        let mut a = step("a", &[]);
+        a.retry = Some(RetryPolicy {
+            max_attempts: 2,
+            backoff: Duration::from_secs(60),
+            exit_codes: vec![],
+        });
+        let cancellation = CancellationToken::new();
+        let mut b = build(vec![a]).with_cancellation(cancellation.clone());
+        tokio::spawn(async move {
+            time::sleep(Duration::from_secs(1)).await;
+            cancellation.cancel();
+        });
+        b.run(&conn).await;
+        let summary = b.summary().unwrap();
+        assert_eq!(summary.steps[0].result, StepResult::StepCancelled);
+        assert_eq!(summary.steps[0].attempts, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelled_build_starts_nothing() {
+        let conn = FakeContainers::default();
+        let cancellation = CancellationToken::new();
+        cancellation.cancel();
+        let mut b = build(vec![step("a", &[])]).with_cancellation(cancellation);
+        let (res, steps) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildCancelled);
+        assert_eq!(result(&steps, "a"), StepResult::StepSkipped);
+        assert!(conn.started().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn missing_secrets_fail_the_build_before_it_starts() {
+        let conn = FakeContainers::default();
+        let mut b = build(vec![step("a", &[])]);
+        b.pipeline.secrets = vec!["CI_RS_TEST_MISSING_SECRET".to_string()];
+        let (res, steps) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildFailed);
+        assert_eq!(steps.len(), 1);
+        assert_eq!(result(&steps, "a"), StepResult::StepSkipped);
+        assert!(conn.started().is_empty());
+        assert_eq!(conn.leftovers(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pulls_images_according_to_pull_policy() {
+        let conn = FakeContainers::default().image("busybox:1");
+        let mut local = step("local", &[]);
+        local.image = "busybox:1".into();
+        let mut never = step("never", &[]);
+        never.image = "busybox:2".into();
+        never.pull_policy = PullPolicy::Never;
+        let mut always = step("always", &[]);
+        always.image = "busybox:1".into();
+        always.pull_policy = PullPolicy::Always;
+        let mut b = build(vec![step("missing", &[]), local, never, always]);
+        let mut events = b.subscribe();
+        b.run(&conn).await;
+        assert_eq!(conn.pulled(), vec!["alpine:3", "busybox:1"]);
+        let mut pulls = vec![];
+        while let Ok(event) = events.try_recv() {
+            if let BuildEvent::StepPull { step, status, .. } = event {
+                pulls.push(format!("{}: {status}", step.0));
+            }
+        }
+        assert_eq!(
+            pulls,
+            vec![
+                "missing: Downloaded newer image for alpine:3",
+                "always: Downloaded newer image for busybox:1"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn passes_environment_and_workspace_to_steps() {
+        let conn = FakeContainers::default();
+        let mut a = step("a", &[]);
+        a.env.insert("B".to_string(), "step".to_string());
+        let mut b = build(vec![a]);
+        b.pipeline
+            .env
+            .insert("A".to_string(), "pipeline".to_string());
+        b.pipeline
+            .env
+            .insert("B".to_string(), "pipeline".to_string());
+        b.run(&conn).await;
+        let config = conn.config("a").unwrap();
+        assert_eq!(
+            config.env,
+            Some(vec!["A=pipeline".to_string(), "B=step".to_string()])
+        );
+        assert_eq!(config.working_dir.as_deref(), Some(WORKSPACE_DIR));
+        let mounts = config.host_config.and_then(|h| h.mounts).unwrap();
+        assert_eq!(mounts[0].target.as_deref(), Some(WORKSPACE_DIR));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn progress_does_nothing_once_finished() {
+        let conn = FakeContainers::default();
+        let mut b = build(vec![step("a", &[])]);
+        b.run(&conn).await;
+        let finished_at = b.finished_at;
+        b.progress(&conn).await;
+        assert_eq!(
+            b.state,
+            BuildState::BuildFinished(BuildResult::BuildSucceeded)
+        );
+        assert_eq!(b.completed_steps.len(), 1);
+        assert_eq!(b.finished_at, finished_at);
+        assert_eq!(conn.started().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn emits_events_in_order() {
+        let runs = vec![FakeRun::exit(1), FakeRun::exit(0).stdout("hi")];
+        let conn = FakeContainers::default().script("a", runs);
+        let mut a = step("a", &[]);
+        a.retry = Some(RetryPolicy {
+            max_attempts: 2,
+            backoff: Duration::from_secs(1),
+            exit_codes: vec![],
+        });
+        let mut b = build(vec![a]);
+        let mut events = b.subscribe();
+        b.run(&conn).await;
+        let mut names = vec![];
+        while let Ok(event) = events.try_recv() {
+            names.push(match event {
+                BuildEvent::BuildStarted { .. } => "build started",
+                BuildEvent::BuildRejected { .. } => "build rejected",
+                BuildEvent::StepPull { .. } => "step pull",
+                BuildEvent::StepStarted { .. } => "step started",
+                BuildEvent::StepLog { .. } => "step log",
+                BuildEvent::StepRetrying { .. } => "step retrying",
+                BuildEvent::StepFinished { .. } => "step finished",
+                BuildEvent::BuildTimedOut { .. } => "build timed out",
+                BuildEvent::DockerError { .. } => "docker error",
+                BuildEvent::BuildFinished { .. } => "build finished",
+            });
+        }
+        assert_eq!(
+            names,
+            vec![
+                "build started",
+                "step pull",
+                "step started",
+                "step retrying",
+                "step started",
+                "step log",
+                "step finished",
+                "build finished"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reports_failed_docker_calls_as_events() {
+        let conn = FakeContainers::default().script(
+            "a",
+            vec![FakeRun::exit(0)
+                .failing(FakeFault::Wait)
+                .lasting(Duration::from_secs(5))],
+        );
+        let mut b = build(vec![step("a", &[])]);
+        let mut events = b.subscribe();
+        b.run(&conn).await;
+        let mut errors = vec![];
+        while let Ok(event) = events.try_recv() {
+            if let BuildEvent::DockerError { step, error, .. } = event {
+                errors.push((step.map(|s| s.0), error));
+            }
+        }
+        assert_eq!(
+            errors,
+            vec![(
+                Some("a".to_string()),
+                "Docker responded with status code 500: connection reset while waiting".to_string()
+            )]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn container_names_stay_unique_after_sanitizing() {
+        let conn = FakeContainers::default();
+        let mut b = build(vec![step("a b", &[]), step("a/b", &[]), step("a-b", &[])]);
+        b.id = BuildId::from("x");
+        let (res, _) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildSucceeded);
+        assert_eq!(
+            conn.created(),
+            vec!["nova-x-1-a-b", "nova-x-2-a-b", "nova-x-3-a-b"]
+        );
+        // The audit trail follows this runner's containers by their label.
+        let labels = conn.config("a b").unwrap().labels;
+        assert_eq!(labels.get(RUNNER_ID_LABEL), Some(&b.runner.0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn redacts_secrets_from_logs_and_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secrets.env");
+        std::fs::write(&path, "TOKEN=s3cr3t\n").unwrap();
+        let secrets = Secrets::from_file(&path).unwrap();
+        let run = FakeRun::exit(0).stdout("token is s3cr3t").stderr("s3cr3t!");
+        let conn = FakeContainers::default().script("a", vec![run]);
+        let mut b = build(vec![step("a", &[])]).with_secrets(secrets);
+        b.pipeline.secrets = vec!["TOKEN".to_string()];
+        let mut events = b.subscribe();
+        let (res, steps) = b.run(&conn).await;
+        assert_eq!(res, BuildResult::BuildSucceeded);
+
+        let expected = vec![
+            format!("token is {}\n", secrets::REDACTED),
+            format!("{}!\n", secrets::REDACTED),
+        ];
+        let logs: Vec<_> = steps[0].attempts[0]
+            .logs
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(logs, expected);
+        let mut streamed = vec![];
+        while let Ok(event) = events.try_recv() {
+            if let BuildEvent::StepLog { line, .. } = event {
+                streamed.push(line.to_string());
+            }
+        }
+        assert_eq!(streamed, expected);
+        // The container still gets the real value.
+        let env = conn.config("a").unwrap().env.unwrap();
+        assert!(env.contains(&"TOKEN=s3cr3t".to_string()));
     }
 }
@@ -0,0 +1,99 @@
+use std::time::{Duration, SystemTime};
+
+use crate::docker::utils::LogOutput;
+
+use super::{BuildId, BuildResult, ContainerExitCode, StepName, StepResult};
+
+/// How many events a subscriber may fall behind by before it starts missing
+/// the oldest ones.
+pub const EVENT_CAPACITY: usize = 1024;
+
+/// Something that happened during a build, as seen by subscribers of
+/// [`super::build::Build::subscribe`].
+#[derive(Debug, Clone)]
+pub enum BuildEvent {
+    BuildStarted {
+        build: BuildId,
+        at: SystemTime,
+    },
+    /// The pipeline cannot run, as it is invalid or secrets are missing; the
+    /// build fails without starting any step.
+    BuildRejected {
+        reason: String,
+        at: SystemTime,
+    },
+    /// Progress pulling a step's image before its container is created, for
+    /// one of the image's layers if `layer` is given.
+    StepPull {
+        step: StepName,
+        image: String,
+        layer: Option<String>,
+        status: String,
+        at: SystemTime,
+    },
+    /// A step's container started; `attempt` is 1-based.
+    StepStarted {
+        step: StepName,
+        attempt: usize,
+        at: SystemTime,
+    },
+    /// A line of a step's output, with secrets redacted, sent as the step
+    /// writes it.
+    StepLog {
+        step: StepName,
+        line: LogOutput,
+        at: SystemTime,
+    },
+    /// An attempt of a step failed and the step runs again after `delay`.
+    StepRetrying {
+        step: StepName,
+        /// The attempt that failed, 1-based.
+        attempt: usize,
+        exit_code: ContainerExitCode,
+        delay: Duration,
+        at: SystemTime,
+    },
+    /// A step will not run again, whether it ran or was skipped.
+    StepFinished {
+        step: StepName,
+        result: StepResult,
+        /// How many times the step ran.
+        attempts: usize,
+        at: SystemTime,
+    },
+    /// The build ran out of time; its running steps are killed.
+    BuildTimedOut {
+        at: SystemTime,
+    },
+    /// A Docker call failed, for `step` if it was about one. Failing to pull
+    /// a step's image or to start its container fails that step and skips its
+    /// dependents, failing to wait on a step fails the build, other failures
+    /// are only reported.
+    DockerError {
+        step: Option<StepName>,
+        error: String,
+        at: SystemTime,
+    },
+    BuildFinished {
+        result: BuildResult,
+        at: SystemTime,
+    },
+}
+
+impl BuildEvent {
+    /// When the event happened.
+    pub fn at(&self) -> SystemTime {
+        match self {
+            BuildEvent::BuildStarted { at, .. }
+            | BuildEvent::BuildRejected { at, .. }
+            | BuildEvent::StepPull { at, .. }
+            | BuildEvent::StepStarted { at, .. }
+            | BuildEvent::StepLog { at, .. }
+            | BuildEvent::StepRetrying { at, .. }
+            | BuildEvent::StepFinished { at, .. }
+            | BuildEvent::BuildTimedOut { at, .. }
+            | BuildEvent::DockerError { at, .. }
+            | BuildEvent::BuildFinished { at, .. } => *at,
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use serde_path_to_error::Segment;
+
+use super::Pipeline;
+
+/// Pipeline file the runner looks for when no path is given.
+pub const DEFAULT_PIPELINE_FILE: &str = ".ci-rs.yml";
+
+/// Format of a pipeline file, chosen from its extension.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PipelineFormat {
+    Yaml,
+    Json,
+}
+
+impl PipelineFormat {
+    /// `.json` files are read as JSON, anything else as YAML.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => PipelineFormat::Json,
+            _ => PipelineFormat::Yaml,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PipelineFileError {
+    /// Error emitted when the pipeline file cannot be read from disk.
+    #[error("Cannot read pipeline file {path}: {err}")]
+    ReadError {
+        /// Path of the pipeline file.
+        path: PathBuf,
+        /// The original error emitted.
+        #[source]
+        err: std::io::Error,
+    },
+    /// Error emitted when the pipeline file does not describe a valid pipeline.
+    #[error("{}: {err}", path.display())]
+    ParseError {
+        /// Path of the pipeline file.
+        path: PathBuf,
+        /// Where and why deserialization failed.
+        #[source]
+        err: Box<PipelineParseError>,
+    },
+}
+
+/// Describes where and why a pipeline definition failed to deserialize.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PipelineParseError {
+    /// Path of the offending field, e.g. `steps[1].image`.
+    pub field: String,
+    /// Position of the offending step in `steps`, when the error is inside one.
+    pub step_index: Option<usize>,
+    /// Name of the offending step, when it could be recovered from the document.
+    pub step_name: Option<String>,
+    /// 1-based line of the error.
+    pub line: Option<usize>,
+    /// 1-based column of the error.
+    pub column: Option<usize>,
+    /// Reason reported by the deserializer.
+    pub message: String,
+}
+
+impl std::error::Error for PipelineParseError {}
+
+impl fmt::Display for PipelineParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.step_index, &self.step_name) {
+            (Some(index), Some(name)) => write!(f, "in step #{} `{name}` ", index + 1)?,
+            (Some(index), None) => write!(f, "in step #{} ", index + 1)?,
+            _ => {}
+        }
+        if !self.field.is_empty() && self.field != "." {
+            write!(f, "at `{}`: ", self.field)?;
+        }
+        write!(f, "{}", self.message)?;
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, " (line {line}, column {column})")?;
+        }
+        Ok(())
+    }
+}
+
+impl Pipeline {
+    /// Load a pipeline from a YAML or JSON file, see [`PipelineFormat::from_path`].
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Pipeline, PipelineFileError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|err| PipelineFileError::ReadError {
+            path: path.to_path_buf(),
+            err,
+        })?;
+        Pipeline::parse(&contents, PipelineFormat::from_path(path)).map_err(|err| {
+            PipelineFileError::ParseError {
+                path: path.to_path_buf(),
+                err: Box::new(err),
+            }
+        })
+    }
+
+    /// Parse a pipeline definition held in memory.
+    pub fn parse(contents: &str, format: PipelineFormat) -> Result<Pipeline, PipelineParseError> {
+        match format {
+            PipelineFormat::Yaml => {
+                serde_path_to_error::deserialize(serde_yaml::Deserializer::from_str(contents))
+                    .map_err(|err| {
+                        let location = err.inner().location();
+                        PipelineParseError::new(
+                            contents,
+                            err.path(),
+                            location.as_ref().map(|l| l.line()),
+                            location.as_ref().map(|l| l.column()),
+                            err.inner().to_string(),
+                        )
+                    })
+            }
+            PipelineFormat::Json => {
+                let mut de = serde_json::Deserializer::from_str(contents);
+                serde_path_to_error::deserialize(&mut de).map_err(|err| {
+                    // serde_json reports 0 when the error has no position
+                    let line = Some(err.inner().line()).filter(|l| *l > 0);
+                    let column = Some(err.inner().column()).filter(|c| *c > 0);
+                    PipelineParseError::new(
+                        contents,
+                        err.path(),
+                        line,
+                        column,
+                        err.inner().to_string(),
+                    )
+                })
+            }
+        }
+    }
+}
+
+impl PipelineParseError {
+    fn new(
+        contents: &str,
+        path: &serde_path_to_error::Path,
+        line: Option<usize>,
+        column: Option<usize>,
+        message: String,
+    ) -> Self {
+        let field = path.to_string();
+        let step_index = PipelineParseError::step_index(path);
+        let step_name = step_index.and_then(|index| {
+            // YAML is a superset of JSON, so this covers both formats.
+            let doc: serde_yaml::Value = serde_yaml::from_str(contents).ok()?;
+            doc.get("steps")?
+                .get(index)?
+                .get("name")?
+                .as_str()
+                .map(String::from)
+        });
+        let message = PipelineParseError::strip_context(&field, message);
+        PipelineParseError {
+            field,
+            step_index,
+            step_name,
+            line,
+            column,
+            message,
+        }
+    }
+
+    fn step_index(path: &serde_path_to_error::Path) -> Option<usize> {
+        let mut segments = path.iter();
+        match (segments.next(), segments.next()) {
+            (Some(Segment::Map { key }), Some(Segment::Seq { index })) if key == "steps" => {
+                Some(*index)
+            }
+            _ => None,
+        }
+    }
+
+    /// The deserializers embed their own field path and position in the message,
+    /// which are reported separately here.
+    fn strip_context(field: &str, message: String) -> String {
+        let mut message = match message.rfind(" at line ") {
+            Some(pos) if message[pos..].contains(" column ") => message[..pos].to_string(),
+            _ => message,
+        };
+        let mut prefix = field;
+        while !prefix.is_empty() {
+            if let Some(rest) = message.strip_prefix(&format!("{prefix}: ")) {
+                message = rest.to_string();
+                break;
+            }
+            prefix = match prefix.rfind(['.', '[']) {
+                Some(pos) => &prefix[..pos],
+                None => "",
+            };
+        }
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml_error(contents: &str) -> PipelineParseError {
+        Pipeline::parse(contents, PipelineFormat::Yaml).unwrap_err()
+    }
+
+    #[test]
+    fn reports_missing_field_with_step_name_and_position() {
+        let err = yaml_error("steps:\n  - name: build\n    commands: [date]\n");
+        assert_eq!(
+            err,
+            PipelineParseError {
+                field: "steps[0]".to_string(),
+                step_index: Some(0),
+                step_name: Some("build".to_string()),
+                line: Some(2),
+                column: Some(5),
+                message: "missing field `image`".to_string(),
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "in step #1 `build` at `steps[0]`: missing field `image` (line 2, column 5)"
+        );
+    }
+
+    #[test]
+    fn reports_unknown_field() {
+        let err = yaml_error(
+            "steps:\n  - name: build\n    image: alpine\n    commands: [date]\n    imagee: b\n",
+        );
+        assert_eq!(err.field, "steps[0].imagee");
+        assert_eq!((err.line, err.column), (Some(5), Some(5)));
+        assert!(
+            err.message
+                .starts_with("unknown field `imagee`, expected one of"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn reports_wrong_type_in_a_later_step() {
+        let err = yaml_error(concat!(
+            "steps:\n",
+            "  - name: build\n    image: alpine\n    commands: [date]\n",
+            "  - name: test\n    image: alpine\n    commands: [date]\n    timeout: soon\n",
+        ));
+        assert_eq!(
+            err.to_string(),
+            "in step #2 `test` at `steps[1].timeout`: invalid type: string \"soon\", \
+             expected u64 (line 8, column 14)"
+        );
+    }
+
+    #[test]
+    fn reports_json_errors_like_yaml_ones() {
+        let contents = r#"{"steps": [{"name": "build", "image": "alpine", "commands": 3}]}"#;
+        let err = Pipeline::parse(contents, PipelineFormat::Json).unwrap_err();
+        assert_eq!(err.field, "steps[0].commands");
+        assert_eq!(err.step_name.as_deref(), Some("build"));
+        assert_eq!((err.line, err.column), (Some(1), Some(61)));
+        // serde_json's own " at line 1 column 61" is reported separately.
+        assert_eq!(
+            err.message,
+            "invalid type: integer `3`, expected a sequence"
+        );
+    }
+
+    #[test]
+    fn errors_outside_steps_have_no_step() {
+        let err = yaml_error("steps: []\n");
+        assert_eq!((err.step_index, err.step_name.as_deref()), (None, None));
+        assert!(err.to_string().starts_with("at `steps`: "), "{err}");
+    }
+
+    #[test]
+    fn strip_context_removes_field_prefix_and_position() {
+        let strip = PipelineParseError::strip_context;
+        assert_eq!(
+            strip(
+                "steps[0].image",
+                "steps[0]: missing field `x` at line 3 column 5".to_string()
+            ),
+            "missing field `x`"
+        );
+        assert_eq!(
+            strip("steps", "the line was empty".to_string()),
+            "the line was empty"
+        );
+    }
+
+    #[test]
+    fn from_file_picks_format_from_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let json = dir.path().join("pipeline.json");
+        fs::write(
+            &json,
+            r#"{"steps": [{"name": "build", "image": "alpine", "commands": ["date"]}]}"#,
+        )
+        .unwrap();
+        let pipeline = Pipeline::from_file(&json).unwrap();
+        assert_eq!(pipeline.steps.head.name.0, "build");
+
+        let yaml = dir.path().join("pipeline.yml");
+        fs::write(&yaml, "steps: []\n").unwrap();
+        let err = Pipeline::from_file(&yaml).unwrap_err();
+        assert!(matches!(err, PipelineFileError::ParseError { .. }), "{err}");
+        assert!(err
+            .to_string()
+            .starts_with(&format!("{}: ", yaml.display())));
+
+        let missing = Pipeline::from_file(dir.path().join("missing.yml")).unwrap_err();
+        assert!(matches!(missing, PipelineFileError::ReadError { .. }));
+    }
+}
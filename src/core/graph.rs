@@ -0,0 +1,147 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{build::CompletedSteps, Pipeline, StepName, StepResult};
+
+/// Whether a pending step can be started given the steps completed so far.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Readiness {
+    /// Every dependency completed successfully.
+    Ready,
+    /// Some dependencies have not completed yet.
+    Waiting,
+    /// A dependency failed or was skipped, so the step can never run.
+    Unreachable,
+}
+
+/// Dependency graph of a pipeline, built from each step's `depends_on`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct StepGraph {
+    /// Step names in declaration order.
+    steps: Vec<StepName>,
+    dependencies: HashMap<StepName, Vec<StepName>>,
+}
+
+impl StepGraph {
+    pub fn new(pipeline: &Pipeline) -> Self {
+        let steps = pipeline.steps.iter().map(|s| s.name.clone()).collect();
+        let dependencies = pipeline
+            .steps
+            .iter()
+            .map(|s| (s.name.clone(), s.depends_on.clone().unwrap_or_default()))
+            .collect();
+        StepGraph {
+            steps,
+            dependencies,
+        }
+    }
+
+    pub fn dependencies(&self, step: &StepName) -> &[StepName] {
+        self.dependencies
+            .get(step)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn readiness(&self, step: &StepName, completed_steps: &CompletedSteps) -> Readiness {
+        let mut readiness = Readiness::Ready;
+        for dependency in self.dependencies(step) {
+            match completed_steps.iter().find(|step| &step.name == dependency) {
+                Some(step) if step.result == StepResult::StepSucceeded => {}
+                Some(_) => return Readiness::Unreachable,
+                None => readiness = Readiness::Waiting,
+            }
+        }
+        readiness
+    }
+}
+
+impl StepGraph {
+    /// Every dependency cycle in the graph, each listed from the first step
+    /// reached in declaration order and closed with that same step, e.g.
+    /// `[a, b, a]`. Dependencies on unknown steps are ignored.
+    pub fn cycles(&self) -> Vec<Vec<StepName>> {
+        let mut cycles = vec![];
+        let mut visited = HashSet::new();
+        let mut stack = vec![];
+        for step in &self.steps {
+            self.find_cycles(step, &mut visited, &mut stack, &mut cycles);
+        }
+        cycles
+    }
+
+    fn find_cycles(
+        &self,
+        step: &StepName,
+        visited: &mut HashSet<StepName>,
+        stack: &mut Vec<StepName>,
+        cycles: &mut Vec<Vec<StepName>>,
+    ) {
+        if let Some(pos) = stack.iter().position(|s| s == step) {
+            let mut cycle = stack[pos..].to_vec();
+            cycle.push(step.clone());
+            cycles.push(cycle);
+            return;
+        }
+        if !visited.insert(step.clone()) {
+            return;
+        }
+        stack.push(step.clone());
+        for dependency in self.dependencies(step) {
+            if self.dependencies.contains_key(dependency) {
+                self.find_cycles(dependency, visited, stack, cycles);
+            }
+        }
+        stack.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nonempty::NonEmpty;
+
+    use super::*;
+    use crate::core::{build::CompletedStep, testing::step, ContainerExitCode};
+
+    fn graph(steps: &[(&str, &[&str])]) -> StepGraph {
+        let steps = steps
+            .iter()
+            .map(|(name, depends_on)| step(name, depends_on));
+        StepGraph::new(&Pipeline::new(NonEmpty::from_vec(steps.collect()).unwrap()))
+    }
+
+    fn names(names: &[&str]) -> Vec<StepName> {
+        names.iter().map(|&n| n.into()).collect()
+    }
+
+    #[test]
+    fn finds_no_cycles_in_a_dag() {
+        let g = graph(&[("a", &[]), ("b", &["a"]), ("c", &["a", "b"])]);
+        assert!(g.cycles().is_empty());
+    }
+
+    #[test]
+    fn finds_each_cycle_once() {
+        let g = graph(&[
+            ("a", &["a"]),
+            ("b", &["c"]),
+            ("c", &["d"]),
+            ("d", &["b", "missing"]),
+        ]);
+        assert_eq!(
+            g.cycles(),
+            vec![names(&["a", "a"]), names(&["b", "c", "d", "b"])]
+        );
+    }
+
+    #[test]
+    fn readiness_follows_dependency_results() {
+        let g = graph(&[("a", &[]), ("b", &[]), ("c", &["a", "b"])]);
+        let c = StepName::from("c");
+        let ok = |name: &str| CompletedStep::new(name.into(), StepResult::StepSucceeded);
+        let failed = CompletedStep::new("b".into(), StepResult::StepFailed(ContainerExitCode(1)));
+        assert_eq!(g.readiness(&"a".into(), &vec![]), Readiness::Ready);
+        assert_eq!(g.readiness(&c, &vec![ok("a")]), Readiness::Waiting);
+        assert_eq!(g.readiness(&c, &vec![ok("a"), ok("b")]), Readiness::Ready);
+        assert_eq!(g.readiness(&c, &vec![failed]), Readiness::Unreachable);
+    }
+}
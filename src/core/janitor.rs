@@ -0,0 +1,473 @@
+//! Removal of the containers, volumes and networks that builds leave behind
+//! when they do not get to clean up after themselves, such as when the runner
+//! dies mid-build.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use derive_new::new;
+
+use crate::{
+    docker::{
+        container::{ListContainersOptions, RemoveContainerOptions},
+        errors::Error,
+        network::ListNetworksOptions,
+        volume::{ListVolumesOptions, RemoveVolumeOptions},
+    },
+    BuildId, RunnerId,
+};
+
+use super::{
+    build::{BUILD_LABEL, RUNNER_ID_LABEL, RUNNER_LABEL},
+    runtime::ContainerService,
+};
+
+/// How often the runner looks for orphaned resources unless told otherwise.
+pub const DEFAULT_JANITOR_INTERVAL: Duration = Duration::from_secs(300);
+
+/// How long the resources of another runner's build are left alone unless told
+/// otherwise.
+pub const DEFAULT_JANITOR_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ResourceKind {
+    Container,
+    Volume,
+    Network,
+}
+
+impl fmt::Display for ResourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResourceKind::Container => write!(f, "container"),
+            ResourceKind::Volume => write!(f, "volume"),
+            ResourceKind::Network => write!(f, "network"),
+        }
+    }
+}
+
+/// A resource carrying the runner's label.
+#[derive(Debug, PartialEq, Eq, Clone, new)]
+pub struct Resource {
+    pub kind: ResourceKind,
+    /// What the resource is removed by.
+    pub id: String,
+    pub name: String,
+    /// The build that created it, if it is labelled with one.
+    pub build: Option<BuildId>,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.kind, self.name)?;
+        if let Some(build) = &self.build {
+            write!(f, " (build {})", build.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a sweep removed, or would have in a dry run.
+#[derive(Debug, Default)]
+pub struct Sweep {
+    pub removed: Vec<Resource>,
+    /// Resources that could not be removed; the next sweep tries again.
+    pub failed: Vec<(Resource, Error)>,
+}
+
+/// Finds the resources labelled by the runner that belong to no active build
+/// and removes them.
+///
+/// The resources `runner` labelled are its own: those of a build that is not
+/// active are what a crashed run left behind, and go right away. Runners
+/// sharing a daemon leave each other's builds alone for a while instead, as
+/// such a build may have no container running while it pulls an image, waits
+/// to retry a step or moves on to the next one: all of its resources are kept
+/// as long as one of them is younger than `max_age`, whatever state its
+/// containers are in. Resources with no runner id are treated as another
+/// runner's.
+#[derive(Debug, Clone, new)]
+pub struct Janitor {
+    /// The runner whose resources belong to no build but the active ones.
+    #[new(default)]
+    pub runner: Option<RunnerId>,
+    /// Builds whose resources are left alone.
+    #[new(default)]
+    pub active: HashSet<BuildId>,
+    /// How long after creating its last resource another runner's build is
+    /// taken for the leftover of a runner that died. It should outlast the
+    /// longest build.
+    #[new(value = "DEFAULT_JANITOR_MAX_AGE")]
+    pub max_age: Duration,
+    /// Report what would be removed without removing anything.
+    #[new(default)]
+    pub dry_run: bool,
+}
+
+impl Janitor {
+    /// Sweep for `runner`, removing its resources as soon as their build is
+    /// not active.
+    pub fn with_runner(mut self, runner: RunnerId) -> Self {
+        self.runner = Some(runner);
+        self
+    }
+
+    /// Leave the resources of `build` alone.
+    pub fn with_active(mut self, build: BuildId) -> Self {
+        self.active.insert(build);
+        self
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+}
+
+impl Janitor {
+    /// Every resource labelled by the runner and not owned by an active build
+    /// or one another runner may still drive: containers first, then the
+    /// volumes and networks they may use.
+    pub async fn orphans(&self, conn: &impl ContainerService) -> Result<Vec<Resource>, Error> {
+        let filters = || HashMap::from([("label".to_string(), vec![RUNNER_LABEL.to_string()])]);
+        let now = SystemTime::now();
+        let mut resources = vec![];
+        // Builds of other runners that may still be going on.
+        let mut live = HashSet::new();
+
+        let options = ListContainersOptions::new(true, filters());
+        for container in conn.list_containers(Some(options)).await? {
+            let build = build_of(container.labels.as_ref());
+            let created = container
+                .created
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64));
+            if self.in_use(container.labels.as_ref(), created, now) {
+                live.extend(build);
+                continue;
+            }
+            let id = container.id.unwrap_or_default();
+            let name = container
+                .names
+                .and_then(|names| names.into_iter().next())
+                .map(|name| name.trim_start_matches('/').to_string())
+                .unwrap_or_else(|| id.clone());
+            resources.push(Resource::new(ResourceKind::Container, id, name, build));
+        }
+
+        let options = ListVolumesOptions::new(filters());
+        for volume in conn.list_volumes(Some(options)).await?.volumes {
+            let build = build_of(Some(&volume.labels));
+            let created = volume.created_at.map(SystemTime::from);
+            if self.in_use(Some(&volume.labels), created, now) {
+                live.extend(build);
+                continue;
+            }
+            let name = volume.name;
+            resources.push(Resource::new(
+                ResourceKind::Volume,
+                name.clone(),
+                name,
+                build,
+            ));
+        }
+
+        let options = ListNetworksOptions::new(filters());
+        for network in conn.list_networks(Some(options)).await? {
+            let build = build_of(network.labels.as_ref());
+            let created = network.created.map(SystemTime::from);
+            if self.in_use(network.labels.as_ref(), created, now) {
+                live.extend(build);
+                continue;
+            }
+            let name = network.name.unwrap_or_default();
+            let id = network.id.unwrap_or_else(|| name.clone());
+            resources.push(Resource::new(ResourceKind::Network, id, name, build));
+        }
+
+        resources.retain(|r| {
+            r.build
+                .as_ref()
+                .is_none_or(|b| !self.active.contains(b) && !live.contains(b))
+        });
+        Ok(resources)
+    }
+
+    /// Whether a resource may be in use by a build of another runner: it was
+    /// not labelled by this one and was created less than `max_age` ago.
+    fn in_use(
+        &self,
+        labels: Option<&HashMap<String, String>>,
+        created: Option<SystemTime>,
+        now: SystemTime,
+    ) -> bool {
+        let runner = labels.and_then(|labels| labels.get(RUNNER_ID_LABEL));
+        if runner.is_some_and(|runner| self.runner.as_ref().is_some_and(|own| own.0 == *runner)) {
+            return false;
+        }
+        created
+            .is_some_and(|created| now.duration_since(created).unwrap_or_default() < self.max_age)
+    }
+
+    /// Remove the orphaned resources, force-removing containers that still
+    /// run. Only listing them can fail the sweep; removals that fail are
+    /// reported in [`Sweep::failed`].
+    pub async fn sweep(&self, conn: &impl ContainerService) -> Result<Sweep, Error> {
+        let mut sweep = Sweep::default();
+        for resource in self.orphans(conn).await? {
+            if self.dry_run {
+                sweep.removed.push(resource);
+                continue;
+            }
+            let removed = match resource.kind {
+                ResourceKind::Container => {
+                    let options = RemoveContainerOptions::new(true, true, false);
+                    conn.remove_container(&resource.id, Some(options)).await
+                }
+                ResourceKind::Volume => {
+                    let options = RemoveVolumeOptions::new(true);
+                    conn.remove_volume(&resource.id, Some(options)).await
+                }
+                ResourceKind::Network => conn.remove_network(&resource.id).await,
+            };
+            match removed {
+                Ok(()) => sweep.removed.push(resource),
+                Err(err) => sweep.failed.push((resource, err)),
+            }
+        }
+        Ok(sweep)
+    }
+}
+
+fn build_of(labels: Option<&HashMap<String, String>>) -> Option<BuildId> {
+    labels?
+        .get(BUILD_LABEL)
+        .map(|id| BuildId::from(id.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::runtime::fake::FakeContainers;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+    const TWO_DAYS: Duration = Duration::from_secs(2 * 24 * 60 * 60);
+
+    fn daemon() -> FakeContainers {
+        let old = [("nova", ""), ("nova.build", "old")];
+        let live = [("nova", ""), ("nova.build", "live")];
+        FakeContainers::default()
+            .container("nova-old-build", &old, TWO_DAYS)
+            .container("nova-live-build", &live, MINUTE)
+            .container("postgres", &[], MINUTE)
+            .volume("nova-old-workspace", &old, TWO_DAYS)
+            .volume("nova-live-workspace", &live, MINUTE)
+            .network("nova-old-net", &old, TWO_DAYS)
+            .network("nova", &[("nova", "")], TWO_DAYS)
+    }
+
+    #[tokio::test]
+    async fn sweep_removes_orphans_only() {
+        let conn = daemon();
+        let janitor = Janitor::new().with_active(BuildId::from("live"));
+        let sweep = janitor.sweep(&conn).await.unwrap();
+        let mut removed: Vec<_> = sweep.removed.iter().map(ToString::to_string).collect();
+        removed.sort();
+        assert_eq!(
+            removed,
+            vec![
+                "container nova-old-build (build old)",
+                "network nova",
+                "network nova-old-net (build old)",
+                "volume nova-old-workspace (build old)",
+            ]
+        );
+        assert!(sweep.failed.is_empty());
+        assert_eq!(
+            conn.remaining(),
+            vec!["nova-live-build", "nova-live-workspace", "postgres"]
+        );
+    }
+
+    #[tokio::test]
+    async fn dry_run_removes_nothing() {
+        let conn = daemon();
+        let janitor = Janitor::new().with_dry_run(true);
+        let sweep = janitor.sweep(&conn).await.unwrap();
+        assert_eq!(sweep.removed.len(), 4);
+        assert_eq!(conn.leftovers(), 7);
+    }
+
+    const OTHER: [(&str, &str); 3] = [
+        ("nova", ""),
+        ("nova.runner", "other"),
+        ("nova.build", "other"),
+    ];
+
+    const MINE: [(&str, &str); 3] = [("nova", ""), ("nova.runner", "me"), ("nova.build", "mine")];
+
+    fn shared_daemon() -> FakeContainers {
+        FakeContainers::default()
+            .running("nova-other-build", &OTHER, MINUTE)
+            .volume("nova-other-workspace", &OTHER, MINUTE)
+            .network("nova-other-net", &OTHER, MINUTE)
+            .container(
+                "nova-crashed-build",
+                &[
+                    ("nova", ""),
+                    ("nova.runner", "other"),
+                    ("nova.build", "crashed"),
+                ],
+                TWO_DAYS,
+            )
+            .running("nova-mine-build", &MINE, MINUTE)
+    }
+
+    fn janitor() -> Janitor {
+        Janitor::new()
+            .with_runner(RunnerId::from("me"))
+            .with_active(BuildId::from("mine"))
+    }
+
+    async fn remaining_after_sweep(conn: FakeContainers) -> Vec<String> {
+        let janitor = janitor();
+        janitor.sweep(&conn).await.unwrap();
+        conn.remaining()
+    }
+
+    #[tokio::test]
+    async fn leaves_running_builds_of_other_runners_alone() {
+        let conn = shared_daemon();
+        let sweep = janitor().sweep(&conn).await.unwrap();
+        let removed: Vec<_> = sweep.removed.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            removed,
+            vec!["container nova-crashed-build (build crashed)"]
+        );
+        assert_eq!(
+            conn.remaining(),
+            vec![
+                "nova-mine-build",
+                "nova-other-build",
+                "nova-other-net",
+                "nova-other-workspace"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn removes_leftovers_of_this_runner_right_away() {
+        // What a run that crashed a minute ago left behind, still running.
+        let dead = [("nova", ""), ("nova.runner", "me"), ("nova.build", "dead")];
+        let conn = FakeContainers::default()
+            .running("nova-dead-build", &dead, MINUTE)
+            .volume("nova-dead-workspace", &dead, MINUTE)
+            .network("nova-dead-net", &dead, MINUTE)
+            .container("nova-mine-build", &MINE, TWO_DAYS)
+            .volume("nova-mine-workspace", &MINE, TWO_DAYS);
+        assert_eq!(
+            remaining_after_sweep(conn).await,
+            vec!["nova-mine-build", "nova-mine-workspace"]
+        );
+    }
+
+    #[tokio::test]
+    async fn leaves_young_networks_of_other_runners_alone() {
+        let conn = FakeContainers::default()
+            .network("nova-other-net", &OTHER, MINUTE)
+            .network("nova-unlabelled-net", &[("nova", "")], MINUTE)
+            .network(
+                "nova-crashed-net",
+                &[
+                    ("nova", ""),
+                    ("nova.runner", "other"),
+                    ("nova.build", "crashed"),
+                ],
+                TWO_DAYS,
+            );
+        assert_eq!(
+            remaining_after_sweep(conn).await,
+            vec!["nova-other-net", "nova-unlabelled-net"]
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_old_resources_of_builds_other_runners_still_drive() {
+        // The build's network is old, but its latest step only just started.
+        let conn = FakeContainers::default()
+            .running("nova-other-build", &OTHER, MINUTE)
+            .network("nova-other-net", &OTHER, TWO_DAYS);
+        assert_eq!(
+            remaining_after_sweep(conn).await,
+            vec!["nova-other-build", "nova-other-net"]
+        );
+    }
+
+    #[tokio::test]
+    async fn leaves_builds_of_other_runners_alone_between_steps() {
+        let conn = FakeContainers::default()
+            .container("nova-other-build", &OTHER, MINUTE)
+            .container("nova-other-test", &OTHER, MINUTE)
+            .volume("nova-other-workspace", &OTHER, MINUTE)
+            .network("nova-other-net", &OTHER, MINUTE);
+        assert_eq!(
+            remaining_after_sweep(conn).await,
+            vec![
+                "nova-other-build",
+                "nova-other-net",
+                "nova-other-test",
+                "nova-other-workspace"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn leaves_builds_of_other_runners_alone_while_retrying() {
+        // The failed attempt's container is gone and the next one is not
+        // created until the backoff is over.
+        let conn = FakeContainers::default()
+            .volume("nova-other-workspace", &OTHER, MINUTE)
+            .network("nova-other-net", &OTHER, TWO_DAYS);
+        assert_eq!(
+            remaining_after_sweep(conn).await,
+            vec!["nova-other-net", "nova-other-workspace"]
+        );
+    }
+
+    #[tokio::test]
+    async fn removes_builds_of_other_runners_past_max_age() {
+        let conn = shared_daemon();
+        let impatient = janitor().with_max_age(Duration::from_secs(30));
+        impatient.sweep(&conn).await.unwrap();
+        assert_eq!(conn.remaining(), vec!["nova-mine-build"]);
+
+        let conn = shared_daemon();
+        let patient = janitor().with_max_age(Duration::from_secs(3600));
+        patient.sweep(&conn).await.unwrap();
+        assert_eq!(
+            conn.remaining(),
+            vec![
+                "nova-mine-build",
+                "nova-other-build",
+                "nova-other-net",
+                "nova-other-workspace"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn containers_go_before_volumes_and_networks() {
+        let orphans = Janitor::new().orphans(&daemon()).await.unwrap();
+        let kinds: Vec<_> = orphans.iter().map(|r| r.kind).collect();
+        let mut sorted = kinds.clone();
+        sorted.sort_by_key(|k| *k as u8);
+        assert_eq!(kinds, sorted);
+    }
+}
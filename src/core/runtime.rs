@@ -0,0 +1,227 @@
+#[cfg(test)]
+pub mod fake;
+
+use std::future::Future;
+
+use bollard_stubs::models::{
+    ContainerCreateResponse, ContainerInspectResponse, ContainerSummary, ContainerWaitResponse,
+    CreateImageInfo, Image, Network, Volume, VolumeListResponse,
+};
+use futures_core::Stream;
+
+use crate::docker::{
+    container::{
+        AttachContainerOptions, AttachContainerResults, CreateContainerConfig,
+        CreateContainerOptions, InspectContainerOptions, KillContainerOptions,
+        ListContainersOptions, LogsOptions, RemoveContainerOptions, StartContainerOptions,
+        WaitContainerOptions,
+    },
+    errors::Error,
+    image::CreateImageOptions,
+    network::ListNetworksOptions,
+    utils::LogOutput,
+    volume::{CreateVolumeOptions, ListVolumesOptions, RemoveVolumeOptions},
+    Docker,
+};
+
+/// The container operations a build needs, so that it can run against Docker
+/// or anything that behaves like it. A build starts and follows each step from
+/// tasks of their own, with a clone of the service, so the calls they make
+/// return futures that can be sent to them.
+pub trait ContainerService: Clone + Send + Sync + 'static {
+    async fn create_volume(&self, options: CreateVolumeOptions<String>) -> Result<Volume, Error>;
+
+    async fn remove_volume(
+        &self,
+        volume_name: &str,
+        options: Option<RemoveVolumeOptions>,
+    ) -> Result<(), Error>;
+
+    async fn list_volumes(
+        &self,
+        options: Option<ListVolumesOptions<String>>,
+    ) -> Result<VolumeListResponse, Error>;
+
+    async fn list_networks(
+        &self,
+        options: Option<ListNetworksOptions<String>>,
+    ) -> Result<Vec<Network>, Error>;
+
+    async fn remove_network(&self, network_name_or_id: &str) -> Result<(), Error>;
+
+    fn inspect_image(&self, image_name: &str) -> impl Future<Output = Result<Image, Error>> + Send;
+
+    /// Pull an image, streaming the progress of the pull.
+    fn create_image(
+        &self,
+        options: Option<CreateImageOptions<String>>,
+    ) -> impl Stream<Item = Result<CreateImageInfo, Error>> + Send;
+
+    fn create_container(
+        &self,
+        options: Option<CreateContainerOptions<String>>,
+        config: CreateContainerConfig<String>,
+    ) -> impl Future<Output = Result<ContainerCreateResponse, Error>> + Send;
+
+    fn start_container(
+        &self,
+        container_name_or_id: &str,
+        options: Option<StartContainerOptions<String>>,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Wait for the container to stop; the stream yields its exit status.
+    fn wait_container(
+        &self,
+        container_name_or_id: &str,
+        options: Option<WaitContainerOptions<String>>,
+    ) -> impl Stream<Item = Result<ContainerWaitResponse, Error>> + Send;
+
+    fn logs(
+        &self,
+        container_name_or_id: &str,
+        options: Option<LogsOptions<String>>,
+    ) -> impl Stream<Item = Result<LogOutput, Error>> + Send;
+
+    fn attach_container(
+        &self,
+        container_name_or_id: &str,
+        options: Option<AttachContainerOptions<String>>,
+    ) -> impl Future<Output = Result<AttachContainerResults, Error>> + Send;
+
+    async fn kill_container(
+        &self,
+        container_name_or_id: &str,
+        options: Option<KillContainerOptions<String>>,
+    ) -> Result<(), Error>;
+
+    async fn remove_container(
+        &self,
+        container_name_or_id: &str,
+        options: Option<RemoveContainerOptions>,
+    ) -> Result<(), Error>;
+
+    async fn list_containers(
+        &self,
+        options: Option<ListContainersOptions<String>>,
+    ) -> Result<Vec<ContainerSummary>, Error>;
+
+    async fn inspect_container(
+        &self,
+        container_name_or_id: &str,
+        options: Option<InspectContainerOptions>,
+    ) -> Result<ContainerInspectResponse, Error>;
+}
+
+impl ContainerService for Docker {
+    async fn create_volume(&self, options: CreateVolumeOptions<String>) -> Result<Volume, Error> {
+        Docker::create_volume(self, options).await
+    }
+
+    async fn remove_volume(
+        &self,
+        volume_name: &str,
+        options: Option<RemoveVolumeOptions>,
+    ) -> Result<(), Error> {
+        Docker::remove_volume(self, volume_name, options).await
+    }
+
+    async fn list_volumes(
+        &self,
+        options: Option<ListVolumesOptions<String>>,
+    ) -> Result<VolumeListResponse, Error> {
+        Docker::list_volumes(self, options).await
+    }
+
+    async fn list_networks(
+        &self,
+        options: Option<ListNetworksOptions<String>>,
+    ) -> Result<Vec<Network>, Error> {
+        Docker::list_networks(self, options).await
+    }
+
+    async fn remove_network(&self, network_name_or_id: &str) -> Result<(), Error> {
+        Docker::remove_network(self, network_name_or_id).await
+    }
+
+    async fn inspect_image(&self, image_name: &str) -> Result<Image, Error> {
+        Docker::inspect_image(self, image_name).await
+    }
+
+    fn create_image(
+        &self,
+        options: Option<CreateImageOptions<String>>,
+    ) -> impl Stream<Item = Result<CreateImageInfo, Error>> + Send {
+        Docker::create_image(self, options)
+    }
+
+    async fn create_container(
+        &self,
+        options: Option<CreateContainerOptions<String>>,
+        config: CreateContainerConfig<String>,
+    ) -> Result<ContainerCreateResponse, Error> {
+        Docker::create_container(self, options, config).await
+    }
+
+    async fn start_container(
+        &self,
+        container_name_or_id: &str,
+        options: Option<StartContainerOptions<String>>,
+    ) -> Result<(), Error> {
+        Docker::start_container(self, container_name_or_id, options).await
+    }
+
+    fn wait_container(
+        &self,
+        container_name_or_id: &str,
+        options: Option<WaitContainerOptions<String>>,
+    ) -> impl Stream<Item = Result<ContainerWaitResponse, Error>> + Send {
+        Docker::wait_container(self, container_name_or_id, options)
+    }
+
+    fn logs(
+        &self,
+        container_name_or_id: &str,
+        options: Option<LogsOptions<String>>,
+    ) -> impl Stream<Item = Result<LogOutput, Error>> + Send {
+        Docker::logs(self, container_name_or_id, options)
+    }
+
+    async fn attach_container(
+        &self,
+        container_name_or_id: &str,
+        options: Option<AttachContainerOptions<String>>,
+    ) -> Result<AttachContainerResults, Error> {
+        Docker::attach_container(self, container_name_or_id, options).await
+    }
+
+    async fn kill_container(
+        &self,
+        container_name_or_id: &str,
+        options: Option<KillContainerOptions<String>>,
+    ) -> Result<(), Error> {
+        Docker::kill_container(self, container_name_or_id, options).await
+    }
+
+    async fn remove_container(
+        &self,
+        container_name_or_id: &str,
+        options: Option<RemoveContainerOptions>,
+    ) -> Result<(), Error> {
+        Docker::remove_container(self, container_name_or_id, options).await
+    }
+
+    async fn list_containers(
+        &self,
+        options: Option<ListContainersOptions<String>>,
+    ) -> Result<Vec<ContainerSummary>, Error> {
+        Docker::list_containers(self, options).await
+    }
+
+    async fn inspect_container(
+        &self,
+        container_name_or_id: &str,
+        options: Option<InspectContainerOptions>,
+    ) -> Result<ContainerInspectResponse, Error> {
+        Docker::inspect_container(self, container_name_or_id, options).await
+    }
+}
@@ -0,0 +1,733 @@
+//! In-memory [`ContainerService`] that plays back scripted runs, for testing
+//! the build engine without a Docker daemon.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bollard_stubs::models::{
+    ContainerCreateResponse, ContainerInspectResponse, ContainerState, ContainerSummary,
+    ContainerWaitResponse, CreateImageInfo, Image, Network, Volume, VolumeListResponse,
+};
+use futures_core::Stream;
+use futures_util::{future, stream, StreamExt};
+use hyper::body::Bytes;
+use tokio::{
+    io::{self, AsyncReadExt},
+    sync::watch,
+    time,
+};
+
+use crate::{
+    core::{build::STEP_LABEL, script::DEFAULT_SHELL},
+    docker::{
+        container::{
+            AttachContainerOptions, AttachContainerResults, CreateContainerConfig,
+            CreateContainerOptions, InspectContainerOptions, KillContainerOptions,
+            ListContainersOptions, LogsOptions, RemoveContainerOptions, StartContainerOptions,
+            WaitContainerOptions,
+        },
+        errors::Error,
+        image::CreateImageOptions,
+        network::ListNetworksOptions,
+        utils::LogOutput,
+        volume::{CreateVolumeOptions, ListVolumesOptions, RemoveVolumeOptions},
+    },
+};
+
+use super::ContainerService;
+
+/// Exit code of a killed container: 128 + SIGKILL.
+const KILLED: i64 = 137;
+
+/// What a step's container does once started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FakeRun {
+    pub exit_code: i64,
+    pub output: Vec<LogOutput>,
+    /// How long the container runs before exiting on its own.
+    pub duration: Duration,
+    /// Docker call that fails for the container, if any.
+    pub fault: Option<FakeFault>,
+    /// Whether the container is killed for running out of memory.
+    pub oom_killed: bool,
+    /// Whether the container leaves its `stdin` unread until it stops.
+    pub ignores_stdin: bool,
+}
+
+/// A call on a container the fake daemon answers with a server error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FakeFault {
+    /// Starting the container fails; it is created but never runs.
+    Start,
+    /// Waiting on the container fails after the run's duration, while the
+    /// container keeps running until killed.
+    Wait,
+}
+
+impl FakeRun {
+    pub fn exit(exit_code: i64) -> Self {
+        FakeRun {
+            exit_code,
+            output: vec![],
+            duration: Duration::ZERO,
+            fault: None,
+            oom_killed: false,
+            ignores_stdin: false,
+        }
+    }
+
+    /// Run `script` with the local `/bin/sh -c`, as a step's container would,
+    /// and play back its exit code and output: stdout first, then stderr.
+    pub fn shell(script: &str) -> Self {
+        let out = std::process::Command::new(DEFAULT_SHELL)
+            .args(["-c", script])
+            .output()
+            .expect("run the script with the local shell");
+        let mut run = FakeRun::exit(out.status.code().expect("script exited") as i64);
+        for line in String::from_utf8_lossy(&out.stdout).lines() {
+            run = run.stdout(line);
+        }
+        for line in String::from_utf8_lossy(&out.stderr).lines() {
+            run = run.stderr(line);
+        }
+        run
+    }
+
+    /// Write `line` to stdout.
+    pub fn stdout(mut self, line: &str) -> Self {
+        let message = Bytes::from(format!("{line}\n"));
+        self.output.push(LogOutput::StdOut { message });
+        self
+    }
+
+    /// Write `line` to stderr.
+    pub fn stderr(mut self, line: &str) -> Self {
+        let message = Bytes::from(format!("{line}\n"));
+        self.output.push(LogOutput::StdErr { message });
+        self
+    }
+
+    pub fn lasting(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Get the container killed for running out of memory, as Docker reports
+    /// it.
+    pub fn out_of_memory(mut self) -> Self {
+        self.exit_code = KILLED;
+        self.oom_killed = true;
+        self
+    }
+
+    /// Never read `stdin`, so that writing more than the pipe holds blocks
+    /// until the container stops.
+    pub fn ignoring_stdin(mut self) -> Self {
+        self.ignores_stdin = true;
+        self
+    }
+
+    /// Make the daemon fail `fault` for this run's container.
+    pub fn failing(mut self, fault: FakeFault) -> Self {
+        self.fault = Some(fault);
+        self
+    }
+}
+
+struct FakeContainer {
+    name: String,
+    labels: Labels,
+    step: String,
+    run: FakeRun,
+    /// Creation time, in seconds since the epoch.
+    created: i64,
+    started: bool,
+    /// Exit code once the container has stopped.
+    exit: watch::Sender<Option<i64>>,
+}
+
+type Labels = HashMap<String, String>;
+
+#[derive(Default)]
+struct FakeState {
+    /// Runs still to play back, by step name. Steps without one exit with 0.
+    runs: HashMap<String, VecDeque<FakeRun>>,
+    images: HashSet<String>,
+    /// Images whose pull fails.
+    unpullable: HashSet<String>,
+    /// How long pulling an image takes.
+    pull_duration: Duration,
+    /// How long creating a container takes, once it exists.
+    create_duration: Duration,
+    pulled: Vec<String>,
+    /// Labels and creation time of each volume, by name.
+    volumes: HashMap<String, (Labels, SystemTime)>,
+    networks: HashMap<String, (Labels, SystemTime)>,
+    containers: HashMap<String, FakeContainer>,
+    /// Configuration of the last container created for each step.
+    configs: HashMap<String, CreateContainerConfig<String>>,
+    next_id: usize,
+    /// Container names, in the order they were created.
+    created: Vec<String>,
+    /// Everything written to each step's `stdin`, once closed.
+    stdin: HashMap<String, String>,
+    /// Step names, in the order their containers started.
+    started: Vec<String>,
+    killed: Vec<String>,
+    running: usize,
+    max_running: usize,
+}
+
+impl FakeState {
+    fn add_container(&mut self, name: String, labels: Labels, run: FakeRun) -> String {
+        self.next_id += 1;
+        let id = format!("{:064x}", self.next_id);
+        let step = labels.get(STEP_LABEL).cloned().unwrap_or_default();
+        let (exit, _) = watch::channel(None);
+        let container = FakeContainer {
+            name,
+            labels,
+            step,
+            run,
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs() as i64,
+            started: false,
+            exit,
+        };
+        self.containers.insert(id.clone(), container);
+        id
+    }
+
+    /// Stop the container unless it already has, returning whether it did.
+    fn stop(&mut self, id: &str, exit_code: i64) -> bool {
+        let Some(container) = self.containers.get(id) else {
+            return false;
+        };
+        let stopped = container.exit.send_if_modified(|exit| match exit {
+            Some(_) => false,
+            None => {
+                *exit = Some(exit_code);
+                true
+            }
+        });
+        if stopped && container.started {
+            self.running -= 1;
+        }
+        stopped
+    }
+}
+
+/// Cloning it shares the same fake daemon.
+#[derive(Clone, Default)]
+pub struct FakeContainers {
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeContainers {
+    /// Play back `runs`, one per attempt, for the containers of `step`.
+    pub fn script(self, step: &str, runs: Vec<FakeRun>) -> Self {
+        self.lock().runs.insert(step.to_string(), runs.into());
+        self
+    }
+
+    /// Make `image` available without pulling it.
+    pub fn image(self, image: &str) -> Self {
+        self.lock().images.insert(image.to_string());
+        self
+    }
+
+    /// Add a stopped container created `age` ago, as if left behind by an
+    /// earlier run.
+    pub fn container(self, name: &str, labels: &[(&str, &str)], age: Duration) -> Self {
+        let mut state = self.lock();
+        let id = state.add_container(name.to_string(), labels_of(labels), FakeRun::exit(0));
+        let container = state.containers.get_mut(&id).unwrap();
+        container.created -= age.as_secs() as i64;
+        container.exit.send_replace(Some(0));
+        drop(state);
+        self
+    }
+
+    /// Add a container created `age` ago that is still running.
+    pub fn running(self, name: &str, labels: &[(&str, &str)], age: Duration) -> Self {
+        let mut state = self.lock();
+        let id = state.add_container(name.to_string(), labels_of(labels), FakeRun::exit(0));
+        let container = state.containers.get_mut(&id).unwrap();
+        container.created -= age.as_secs() as i64;
+        container.started = true;
+        state.running += 1;
+        drop(state);
+        self
+    }
+
+    /// Make every pull of `image` fail, as for a private image.
+    pub fn unpullable(self, image: &str) -> Self {
+        self.lock().unpullable.insert(image.to_string());
+        self
+    }
+
+    /// Make every image pull take `duration`.
+    pub fn pull_lasting(self, duration: Duration) -> Self {
+        self.lock().pull_duration = duration;
+        self
+    }
+
+    /// Make every container creation take `duration`, the container existing
+    /// from the start.
+    pub fn create_lasting(self, duration: Duration) -> Self {
+        self.lock().create_duration = duration;
+        self
+    }
+
+    /// Add a volume created `age` ago.
+    pub fn volume(self, name: &str, labels: &[(&str, &str)], age: Duration) -> Self {
+        let created = SystemTime::now() - age;
+        self.lock()
+            .volumes
+            .insert(name.to_string(), (labels_of(labels), created));
+        self
+    }
+
+    pub fn network(self, name: &str, labels: &[(&str, &str)], age: Duration) -> Self {
+        let created = SystemTime::now() - age;
+        self.lock()
+            .networks
+            .insert(name.to_string(), (labels_of(labels), created));
+        self
+    }
+
+    /// Names of the containers created, in order.
+    pub fn created(&self) -> Vec<String> {
+        self.lock().created.clone()
+    }
+
+    /// Step names, in the order their containers started.
+    pub fn started(&self) -> Vec<String> {
+        self.lock().started.clone()
+    }
+
+    /// What was written to the step's `stdin`, once it was closed.
+    pub fn stdin(&self, step: &str) -> Option<String> {
+        self.lock().stdin.get(step).cloned()
+    }
+
+    pub fn killed(&self) -> Vec<String> {
+        self.lock().killed.clone()
+    }
+
+    /// Images pulled, as `repository:tag`.
+    pub fn pulled(&self) -> Vec<String> {
+        self.lock().pulled.clone()
+    }
+
+    /// Most containers running at the same time.
+    pub fn max_running(&self) -> usize {
+        self.lock().max_running
+    }
+
+    /// Number of containers, volumes and networks not removed yet.
+    pub fn leftovers(&self) -> usize {
+        let state = self.lock();
+        state.containers.len() + state.volumes.len() + state.networks.len()
+    }
+
+    /// Names of the containers, volumes and networks not removed yet, sorted.
+    pub fn remaining(&self) -> Vec<String> {
+        let state = self.lock();
+        let containers = state.containers.values().map(|c| c.name.clone());
+        let volumes = state.volumes.keys().cloned();
+        let networks = state.networks.keys().cloned();
+        let mut names: Vec<_> = containers.chain(volumes).chain(networks).collect();
+        names.sort();
+        names
+    }
+
+    /// Configuration the last container of `step` was created with.
+    pub fn config(&self, step: &str) -> Option<CreateContainerConfig<String>> {
+        self.lock().configs.get(step).cloned()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap()
+    }
+}
+
+fn labels_of(labels: &[(&str, &str)]) -> Labels {
+    labels
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+/// Whether `labels` match every `label` filter, given as `key` or `key=value`.
+fn matches(filters: Option<&HashMap<String, Vec<String>>>, labels: &Labels) -> bool {
+    let wanted = filters.and_then(|f| f.get("label")).into_iter().flatten();
+    wanted
+        .into_iter()
+        .all(|filter| match filter.split_once('=') {
+            Some((key, value)) => labels.get(key).is_some_and(|v| v == value),
+            None => labels.contains_key(filter),
+        })
+}
+
+fn no_such(what: &str, name: &str) -> Error {
+    Error::DockerResponseServerError {
+        status_code: 404,
+        message: format!("No such {what}: {name}"),
+    }
+}
+
+fn server_error(message: &str) -> Error {
+    Error::DockerResponseServerError {
+        status_code: 500,
+        message: message.to_string(),
+    }
+}
+
+impl ContainerService for FakeContainers {
+    async fn create_volume(&self, options: CreateVolumeOptions<String>) -> Result<Volume, Error> {
+        self.lock().volumes.insert(
+            options.name.clone(),
+            (options.labels.clone(), SystemTime::now()),
+        );
+        Ok(Volume {
+            name: options.name,
+            driver: options.driver,
+            labels: options.labels,
+            ..Default::default()
+        })
+    }
+
+    async fn remove_volume(
+        &self,
+        volume_name: &str,
+        _options: Option<RemoveVolumeOptions>,
+    ) -> Result<(), Error> {
+        match self.lock().volumes.remove(volume_name) {
+            Some(_) => Ok(()),
+            None => Err(no_such("volume", volume_name)),
+        }
+    }
+
+    async fn list_volumes(
+        &self,
+        options: Option<ListVolumesOptions<String>>,
+    ) -> Result<VolumeListResponse, Error> {
+        let filters = options.as_ref().map(|o| &o.filters);
+        let volumes = self
+            .lock()
+            .volumes
+            .iter()
+            .filter(|(_, (labels, _))| matches(filters, labels))
+            .map(|(name, (labels, created))| Volume {
+                name: name.clone(),
+                labels: labels.clone(),
+                created_at: Some((*created).into()),
+                ..Default::default()
+            })
+            .collect();
+        Ok(VolumeListResponse {
+            volumes,
+            warnings: vec![],
+        })
+    }
+
+    async fn list_networks(
+        &self,
+        options: Option<ListNetworksOptions<String>>,
+    ) -> Result<Vec<Network>, Error> {
+        let filters = options.as_ref().map(|o| &o.filters);
+        Ok(self
+            .lock()
+            .networks
+            .iter()
+            .filter(|(_, (labels, _))| matches(filters, labels))
+            .map(|(name, (labels, created))| Network {
+                id: Some(name.clone()),
+                name: Some(name.clone()),
+                labels: Some(labels.clone()),
+                created: Some((*created).into()),
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    async fn remove_network(&self, network_name_or_id: &str) -> Result<(), Error> {
+        match self.lock().networks.remove(network_name_or_id) {
+            Some(_) => Ok(()),
+            None => Err(no_such("network", network_name_or_id)),
+        }
+    }
+
+    async fn inspect_image(&self, image_name: &str) -> Result<Image, Error> {
+        match self.lock().images.contains(image_name) {
+            true => Ok(Image {
+                id: image_name.to_string(),
+                ..Default::default()
+            }),
+            false => Err(no_such("image", image_name)),
+        }
+    }
+
+    fn create_image(
+        &self,
+        options: Option<CreateImageOptions<String>>,
+    ) -> impl Stream<Item = Result<CreateImageInfo, Error>> + Send {
+        let options = options.unwrap_or_default();
+        let image = format!("{}:{}", options.from_image, options.tag);
+        let mut state = self.lock();
+        let pullable = !state.unpullable.contains(&image);
+        if pullable {
+            state.pulled.push(image.clone());
+            state.images.insert(image.clone());
+        }
+        let duration = state.pull_duration;
+        stream::once(async move {
+            time::sleep(duration).await;
+            if !pullable {
+                return Err(Error::DockerStreamError {
+                    error: format!("pull access denied for {image}"),
+                });
+            }
+            Ok(CreateImageInfo {
+                status: Some(format!("Downloaded newer image for {image}")),
+                ..Default::default()
+            })
+        })
+    }
+
+    async fn create_container(
+        &self,
+        options: Option<CreateContainerOptions<String>>,
+        config: CreateContainerConfig<String>,
+    ) -> Result<ContainerCreateResponse, Error> {
+        let (id, duration) = {
+            let mut state = self.lock();
+            let name = options.map(|o| o.name).unwrap_or_default();
+            if !name.is_empty() && state.containers.values().any(|c| c.name == name) {
+                return Err(Error::DockerResponseServerError {
+                    status_code: 409,
+                    message: format!("Conflict. The container name \"/{name}\" is already in use"),
+                });
+            }
+            let step = config.labels.get(STEP_LABEL).cloned().unwrap_or_default();
+            let run = state
+                .runs
+                .get_mut(&step)
+                .and_then(VecDeque::pop_front)
+                .unwrap_or(FakeRun::exit(0));
+            state.created.push(name.clone());
+            let id = state.add_container(name, config.labels.clone(), run);
+            state.configs.insert(step, config);
+            (id, state.create_duration)
+        };
+        time::sleep(duration).await;
+        Ok(ContainerCreateResponse {
+            id,
+            warnings: vec![],
+        })
+    }
+
+    async fn start_container(
+        &self,
+        container_name_or_id: &str,
+        _options: Option<StartContainerOptions<String>>,
+    ) -> Result<(), Error> {
+        let mut state = self.lock();
+        let container = state
+            .containers
+            .get_mut(container_name_or_id)
+            .ok_or_else(|| no_such("container", container_name_or_id))?;
+        let (step, run) = (container.step.clone(), container.run.clone());
+        if run.fault == Some(FakeFault::Start) {
+            return Err(server_error("cannot start container"));
+        }
+        container.started = true;
+        state.started.push(step);
+        state.running += 1;
+        state.max_running = state.max_running.max(state.running);
+        let fake = self.state.clone();
+        let id = container_name_or_id.to_string();
+        if run.fault != Some(FakeFault::Wait) {
+            tokio::spawn(async move {
+                time::sleep(run.duration).await;
+                fake.lock().unwrap().stop(&id, run.exit_code);
+            });
+        }
+        Ok(())
+    }
+
+    fn wait_container(
+        &self,
+        container_name_or_id: &str,
+        _options: Option<WaitContainerOptions<String>>,
+    ) -> impl Stream<Item = Result<ContainerWaitResponse, Error>> + Send {
+        let container = self.lock().containers.get(container_name_or_id).map(|c| {
+            let fault = (c.run.fault == Some(FakeFault::Wait)).then_some(c.run.duration);
+            (c.exit.subscribe(), fault)
+        });
+        let id = container_name_or_id.to_string();
+        stream::once(async move {
+            let (mut exit, fault) = container.ok_or_else(|| no_such("container", &id))?;
+            if let Some(after) = fault {
+                time::sleep(after).await;
+                return Err(server_error("connection reset while waiting"));
+            }
+            let code = exit
+                .wait_for(Option::is_some)
+                .await
+                .map(|code| code.unwrap_or_default())
+                .map_err(|_| no_such("container", &id))?;
+            match code {
+                0 => Ok(ContainerWaitResponse {
+                    status_code: 0,
+                    error: None,
+                }),
+                code => Err(Error::DockerContainerWaitError {
+                    error: String::new(),
+                    code,
+                }),
+            }
+        })
+    }
+
+    fn logs(
+        &self,
+        container_name_or_id: &str,
+        options: Option<LogsOptions<String>>,
+    ) -> impl Stream<Item = Result<LogOutput, Error>> + Send {
+        let follow = options.is_some_and(|o| o.follow);
+        let (output, exit) = match self.lock().containers.get(container_name_or_id) {
+            Some(container) => (
+                container.run.output.iter().cloned().map(Ok).collect(),
+                follow.then(|| container.exit.subscribe()),
+            ),
+            None => (vec![Err(no_such("container", container_name_or_id))], None),
+        };
+        // A followed container's output ends when the container does.
+        let end = stream::once(async move {
+            if let Some(mut exit) = exit {
+                let _ = exit.wait_for(Option::is_some).await;
+            }
+        });
+        stream::iter(output).chain(end.filter_map(|()| future::ready(None)))
+    }
+
+    async fn attach_container(
+        &self,
+        container_name_or_id: &str,
+        _options: Option<AttachContainerOptions<String>>,
+    ) -> Result<AttachContainerResults, Error> {
+        let (step, ignores_stdin, mut exit) = self
+            .lock()
+            .containers
+            .get(container_name_or_id)
+            .map(|c| (c.step.clone(), c.run.ignores_stdin, c.exit.subscribe()))
+            .ok_or_else(|| no_such("container", container_name_or_id))?;
+        let (input, mut read) = io::duplex(4096);
+        let fake = self.state.clone();
+        tokio::spawn(async move {
+            if ignores_stdin {
+                // `read` stays open, and unread, until the container stops.
+                let _ = exit.wait_for(Option::is_some).await;
+                return;
+            }
+            let mut stdin = String::new();
+            if read.read_to_string(&mut stdin).await.is_ok() {
+                fake.lock().unwrap().stdin.insert(step, stdin);
+            }
+        });
+        Ok(AttachContainerResults {
+            output: Box::pin(stream::empty()),
+            input: Box::pin(input),
+        })
+    }
+
+    async fn kill_container(
+        &self,
+        container_name_or_id: &str,
+        _options: Option<KillContainerOptions<String>>,
+    ) -> Result<(), Error> {
+        let mut state = self.lock();
+        let step = state
+            .containers
+            .get(container_name_or_id)
+            .map(|c| c.step.clone())
+            .ok_or_else(|| no_such("container", container_name_or_id))?;
+        if !state.stop(container_name_or_id, KILLED) {
+            return Err(Error::DockerResponseServerError {
+                status_code: 409,
+                message: format!("Container {container_name_or_id} is not running"),
+            });
+        }
+        state.killed.push(step);
+        Ok(())
+    }
+
+    async fn remove_container(
+        &self,
+        container_name_or_id: &str,
+        _options: Option<RemoveContainerOptions>,
+    ) -> Result<(), Error> {
+        let mut state = self.lock();
+        state.stop(container_name_or_id, KILLED);
+        match state.containers.remove(container_name_or_id) {
+            Some(_) => Ok(()),
+            None => Err(no_such("container", container_name_or_id)),
+        }
+    }
+
+    async fn list_containers(
+        &self,
+        options: Option<ListContainersOptions<String>>,
+    ) -> Result<Vec<ContainerSummary>, Error> {
+        let filters = options.as_ref().map(|o| &o.filters);
+        Ok(self
+            .lock()
+            .containers
+            .iter()
+            .filter(|(_, c)| matches(filters, &c.labels))
+            .map(|(id, c)| ContainerSummary {
+                id: Some(id.clone()),
+                names: Some(vec![format!("/{}", c.name)]),
+                labels: Some(c.labels.clone()),
+                created: Some(c.created),
+                state: Some(match (c.started, c.exit.borrow().is_some()) {
+                    (_, true) => "exited".to_string(),
+                    (true, false) => "running".to_string(),
+                    (false, false) => "created".to_string(),
+                }),
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    async fn inspect_container(
+        &self,
+        container_name_or_id: &str,
+        _options: Option<InspectContainerOptions>,
+    ) -> Result<ContainerInspectResponse, Error> {
+        let state = self.lock();
+        let container = state
+            .containers
+            .get(container_name_or_id)
+            .ok_or_else(|| no_such("container", container_name_or_id))?;
+        let exit_code = *container.exit.borrow();
+        Ok(ContainerInspectResponse {
+            id: Some(container_name_or_id.to_string()),
+            name: Some(format!("/{}", container.name)),
+            state: Some(ContainerState {
+                running: Some(container.started && exit_code.is_none()),
+                oom_killed: Some(exit_code.is_some() && container.run.oom_killed),
+                exit_code: Some(exit_code.unwrap_or_default()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,134 @@
+use nonempty::NonEmpty;
+
+use crate::docker::utils::LogOutput;
+
+/// Shell used for steps that do not choose one.
+pub const DEFAULT_SHELL: &str = "/bin/sh";
+
+/// Prefix of the line a step script writes to stderr when one of its commands fails.
+const FAILURE_MARKER: &str = "##[ci-rs] command ";
+
+/// Render a step's commands as a single script run with `<shell> -c`.
+///
+/// Commands run in order in the same shell, so `cd` and variables carry over,
+/// and the script stops at the first failing command (`set -e`). Each command is
+/// echoed before it runs, and on failure the script reports the 1-based position
+/// of the command that failed, which [`failed_command`] reads back.
+pub fn render(commands: &NonEmpty<String>) -> String {
+    let mut script = String::from("set -e\n");
+    script.push_str(&format!(
+        "trap '__ci_rs_status=$?; if [ \"$__ci_rs_status\" -ne 0 ]; then \
+         printf \"\\n{FAILURE_MARKER}%s failed with exit code %s\\n\" \
+         \"$__ci_rs_command\" \"$__ci_rs_status\" >&2; fi' EXIT\n"
+    ));
+    for (index, command) in commands.iter().enumerate() {
+        script.push_str(&format!("__ci_rs_command={}\n", index + 1));
+        script.push_str(&format!("printf '+ %s\\n' {}\n", quote(command)));
+        script.push_str(command);
+        script.push('\n');
+    }
+    script
+}
+
+/// 0-based index of the command that failed, as reported by a script from [`render`].
+///
+/// Only the last marker line on stderr counts, so a command cannot pass off
+/// another one as the failure by printing a marker of its own.
+pub fn failed_command(logs: &[LogOutput]) -> Option<usize> {
+    let line = logs.iter().rev().find_map(|line| match line {
+        LogOutput::StdErr { message } => {
+            let line = String::from_utf8_lossy(message);
+            line.starts_with(FAILURE_MARKER).then(|| line.into_owned())
+        }
+        _ => None,
+    })?;
+    let (position, _) = line[FAILURE_MARKER.len()..].split_once(' ')?;
+    position.parse::<usize>().ok()?.checked_sub(1)
+}
+
+/// Quote `value` as a single shell word.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use nonempty::nonempty;
+
+    use super::*;
+    use crate::core::runtime::fake::FakeRun;
+
+    fn lines(run: &FakeRun) -> Vec<String> {
+        run.output.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn quote_keeps_single_quotes() {
+        assert_eq!(quote("echo hi"), "'echo hi'");
+        assert_eq!(quote("echo 'hi'"), r#"'echo '\''hi'\'''"#);
+        let run = FakeRun::shell(&format!("printf '%s' {}", quote("it's $HOME `x`")));
+        assert_eq!(lines(&run), vec!["it's $HOME `x`\n"]);
+    }
+
+    #[test]
+    fn render_stops_at_the_first_failure() {
+        let script = render(&nonempty!["true".to_string(), "false".to_string()]);
+        let expected = r#"set -e
+trap '__ci_rs_status=$?; if [ "$__ci_rs_status" -ne 0 ]; then printf "\n##[ci-rs] command %s failed with exit code %s\n" "$__ci_rs_command" "$__ci_rs_status" >&2; fi' EXIT
+__ci_rs_command=1
+printf '+ %s\n' 'true'
+true
+__ci_rs_command=2
+printf '+ %s\n' 'false'
+false
+"#;
+        assert_eq!(script, expected);
+    }
+
+    #[test]
+    fn rendered_script_reports_the_failed_command() {
+        let commands = nonempty![
+            "cd /".to_string(),
+            "echo \"in $(pwd)\"".to_string(),
+            "exit 3".to_string(),
+            "echo unreachable".to_string(),
+        ];
+        let run = FakeRun::shell(&render(&commands));
+        assert_eq!(run.exit_code, 3);
+        assert_eq!(
+            lines(&run),
+            vec![
+                "+ cd /\n",
+                "+ echo \"in $(pwd)\"\n",
+                "in /\n",
+                "+ exit 3\n",
+                "\n",
+                "##[ci-rs] command 3 failed with exit code 3\n",
+            ]
+        );
+        assert_eq!(failed_command(&run.output), Some(2));
+    }
+
+    #[test]
+    fn succeeding_script_reports_nothing() {
+        let run = FakeRun::shell(&render(&nonempty!["true".to_string()]));
+        assert_eq!(run.exit_code, 0);
+        assert_eq!(lines(&run), vec!["+ true\n"]);
+        assert_eq!(failed_command(&run.output), None);
+    }
+
+    #[test]
+    fn failed_command_uses_the_last_marker_on_stderr() {
+        let run = FakeRun::exit(1)
+            .stderr("##[ci-rs] command 2 failed with exit code 1")
+            .stderr("##[ci-rs] command 4 failed with exit code 1")
+            .stdout("##[ci-rs] command 1 failed with exit code 1")
+            .stderr("echo ##[ci-rs] command 3 failed with exit code 1")
+            .stderr("##[ci-rs] command 0 failed with exit code 1")
+            .stderr("##[ci-rs] command two failed");
+        assert_eq!(failed_command(&run.output[..4]), Some(3));
+        assert_eq!(failed_command(&run.output[2..4]), None);
+        assert_eq!(failed_command(&run.output[..5]), None);
+        assert_eq!(failed_command(&run.output), None);
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use hyper::body::Bytes;
+
+use crate::docker::utils::LogOutput;
+
+/// What secret values are replaced with in captured output.
+pub const REDACTED: &str = "********";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SecretsError {
+    /// Error emitted when the secrets file cannot be read from disk.
+    #[error("Cannot read secrets file {path}: {err}")]
+    ReadError {
+        /// Path of the secrets file.
+        path: PathBuf,
+        /// The original error emitted.
+        #[source]
+        err: std::io::Error,
+    },
+    /// Error emitted when a line of the secrets file is not `NAME=value`.
+    #[error("{}:{line}: expected `NAME=value`", path.display())]
+    ParseError {
+        /// Path of the secrets file.
+        path: PathBuf,
+        /// 1-based line of the error.
+        line: usize,
+    },
+}
+
+/// Secret values available to a build, by name.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Secrets {
+    values: HashMap<String, String>,
+}
+
+impl Secrets {
+    /// Read `NAME=value` lines from a file. Blank lines and lines starting with
+    /// `#` are ignored, and values may be wrapped in single or double quotes.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Secrets, SecretsError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|err| SecretsError::ReadError {
+            path: path.to_path_buf(),
+            err,
+        })?;
+        let mut values = HashMap::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .filter(|(name, _)| !name.trim().is_empty())
+                .ok_or_else(|| SecretsError::ParseError {
+                    path: path.to_path_buf(),
+                    line: index + 1,
+                })?;
+            let value = value.trim();
+            let value = match (value.chars().next(), value.chars().last()) {
+                (Some('"'), Some('"')) | (Some('\''), Some('\'')) if value.len() >= 2 => {
+                    &value[1..value.len() - 1]
+                }
+                _ => value,
+            };
+            values.insert(name.trim().to_string(), value.to_string());
+        }
+        Ok(Secrets { values })
+    }
+
+    /// Read the named secrets from the runner's own environment, leaving out
+    /// any that are not set.
+    pub fn from_env<I, S>(names: I) -> Secrets
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let values = names
+            .into_iter()
+            .map(Into::into)
+            .filter_map(|name| env::var(&name).ok().map(|value| (name, value)))
+            .collect();
+        Secrets { values }
+    }
+
+    /// Add the secrets from `other` that are not already known.
+    pub fn or(mut self, other: Secrets) -> Secrets {
+        for (name, value) in other.values {
+            self.values.entry(name).or_insert(value);
+        }
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    /// Replace every secret value in the output with [`REDACTED`]. Docker
+    /// splits output into lines, so each line of a multi-line value is hidden
+    /// on its own. Longer values go first, so a secret containing another is
+    /// not left half shown.
+    pub fn redact(&self, output: LogOutput) -> LogOutput {
+        let mut message = String::from_utf8_lossy(output.as_ref()).to_string();
+        let mut redacted = false;
+        let mut values: Vec<&str> = self
+            .values
+            .values()
+            .flat_map(|v| v.lines())
+            .filter(|line| !line.trim().is_empty())
+            .collect();
+        values.sort_by_key(|v| std::cmp::Reverse(v.len()));
+        for value in values {
+            if message.contains(value) {
+                message = message.replace(value, REDACTED);
+                redacted = true;
+            }
+        }
+        if !redacted {
+            return output;
+        }
+        let message = Bytes::from(message);
+        match output {
+            LogOutput::StdErr { .. } => LogOutput::StdErr { message },
+            LogOutput::StdOut { .. } => LogOutput::StdOut { message },
+            LogOutput::StdIn { .. } => LogOutput::StdIn { message },
+            LogOutput::Console { .. } => LogOutput::Console { message },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secrets(values: &[(&str, &str)]) -> Secrets {
+        let values = values
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Secrets { values }
+    }
+
+    fn from_file(contents: &str) -> Result<Secrets, SecretsError> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secrets.env");
+        fs::write(&path, contents).unwrap();
+        Secrets::from_file(path)
+    }
+
+    #[test]
+    fn from_file_reads_quoted_values_and_skips_comments() {
+        let s = from_file(concat!(
+            "# deploy credentials\n",
+            "\n",
+            "TOKEN=abc=def\n",
+            "  PASSWORD = \"hunter 2\"  \n",
+            "KEY='single'\n",
+            "HALF=\"open\n",
+        ))
+        .unwrap();
+        assert_eq!(s.get("TOKEN"), Some("abc=def"));
+        assert_eq!(s.get("PASSWORD"), Some("hunter 2"));
+        assert_eq!(s.get("KEY"), Some("single"));
+        assert_eq!(s.get("HALF"), Some("\"open"));
+        assert_eq!(s.get("# deploy credentials"), None);
+    }
+
+    #[test]
+    fn from_file_reports_the_bad_line() {
+        for contents in ["A=1\n\n# ok\nnot a secret\n", "A=1\nB=2\n\n = empty name\n"] {
+            let err = from_file(contents).unwrap_err();
+            assert!(
+                matches!(err, SecretsError::ParseError { line: 4, .. }),
+                "{err}"
+            );
+            assert!(
+                err.to_string().ends_with(":4: expected `NAME=value`"),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn or_keeps_existing_values() {
+        let s = secrets(&[("A", "1")]).or(secrets(&[("A", "2"), ("B", "3")]));
+        assert_eq!((s.get("A"), s.get("B")), (Some("1"), Some("3")));
+    }
+
+    #[test]
+    fn redact_replaces_every_value() {
+        let s = secrets(&[("A", "abc"), ("B", "xyz"), ("EMPTY", "")]);
+        let line = LogOutput::StdErr {
+            message: Bytes::from("abc then xyz then abc\n"),
+        };
+        assert_eq!(
+            s.redact(line),
+            LogOutput::StdErr {
+                message: Bytes::from(format!("{REDACTED} then {REDACTED} then {REDACTED}\n"))
+            }
+        );
+        let clean = LogOutput::StdOut {
+            message: Bytes::from("nothing to hide\n"),
+        };
+        assert_eq!(s.redact(clean.clone()), clean);
+    }
+
+    #[test]
+    fn redact_hides_longer_values_whole() {
+        let s = secrets(&[("SHORT", "abc"), ("LONG", "abcdef")]);
+        let line = LogOutput::Console {
+            message: Bytes::from("abcdef"),
+        };
+        assert_eq!(s.redact(line).to_string(), REDACTED);
+    }
+
+    #[test]
+    fn redact_hides_each_line_of_multi_line_values() {
+        let s = secrets(&[("KEY", "-----BEGIN KEY-----\r\nabc123\n\n")]);
+        for (line, expected) in [
+            ("-----BEGIN KEY-----\n", format!("{REDACTED}\n")),
+            ("abc123\n", format!("{REDACTED}\n")),
+            ("blank lines stay\n", "blank lines stay\n".to_string()),
+        ] {
+            let line = LogOutput::StdOut {
+                message: Bytes::from(line),
+            };
+            assert_eq!(s.redact(line).to_string(), expected);
+        }
+    }
+}
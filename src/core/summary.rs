@@ -0,0 +1,183 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+use super::{build::Build, BuildResult, BuildState, StepName, StepResult};
+
+/// What a finished build did, step by step.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BuildSummary {
+    pub result: BuildResult,
+    pub started: SystemTime,
+    pub finished: SystemTime,
+    pub duration: Duration,
+    /// Completed steps, in pipeline order.
+    pub steps: Vec<StepSummary>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct StepSummary {
+    pub name: StepName,
+    pub result: StepResult,
+    /// How many times the step's container ran; 0 for skipped steps.
+    pub attempts: usize,
+    /// Start of the first attempt, if the step ran.
+    pub started: Option<SystemTime>,
+    /// End of the last attempt, if the step ran.
+    pub finished: Option<SystemTime>,
+    /// Time from `started` to `finished`, retry backoff included.
+    pub duration: Option<Duration>,
+    /// Whether the last attempt was killed for running out of memory.
+    pub oom_killed: bool,
+}
+
+impl Build {
+    /// Summary of the build once it is finished, `None` before that.
+    pub fn summary(&self) -> Option<BuildSummary> {
+        let BuildState::BuildFinished(result) = &self.state else {
+            return None;
+        };
+        let finished = self.finished_at?;
+        let started = self.started_at.unwrap_or(finished);
+        let steps = self
+            .pipeline
+            .steps
+            .iter()
+            .filter_map(|step| self.completed_steps.iter().find(|s| s.name == step.name))
+            .map(|step| {
+                let started = step.attempts.first().map(|a| a.started);
+                let finished = step.attempts.last().map(|a| a.finished);
+                StepSummary {
+                    name: step.name.clone(),
+                    result: step.result.clone(),
+                    attempts: step.attempts.len(),
+                    started,
+                    finished,
+                    duration: started.zip(finished).map(|(s, f)| elapsed(s, f)),
+                    oom_killed: step.attempts.last().is_some_and(|a| a.oom_killed),
+                }
+            })
+            .collect();
+        Some(BuildSummary {
+            result: result.clone(),
+            started,
+            finished,
+            duration: elapsed(started, finished),
+            steps,
+        })
+    }
+}
+
+/// Wall-clock time between two moments, zero if the clock went backwards.
+fn elapsed(started: SystemTime, finished: SystemTime) -> Duration {
+    finished.duration_since(started).unwrap_or_default()
+}
+
+impl fmt::Display for BuildSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} in {:.1?}", self.result, self.duration)?;
+        for step in &self.steps {
+            write!(f, "\n  {:<20} {:?}", step.name.0, step.result)?;
+            if step.oom_killed {
+                write!(f, " (out of memory)")?;
+            }
+            if let Some(duration) = step.duration {
+                write!(f, " in {:.1?}", duration)?;
+            }
+            if step.attempts > 1 {
+                write!(f, " after {} attempts", step.attempts)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nonempty::{nonempty, NonEmpty};
+
+    use super::*;
+    use crate::core::{
+        build::{CompletedStep, StepAttempt},
+        ContainerExitCode, Pipeline, Step,
+    };
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn attempt(result: StepResult, started: u64, finished: u64) -> StepAttempt {
+        StepAttempt::new(result, at(started), at(finished), vec![], None)
+    }
+
+    fn finished_build() -> Build {
+        let steps = ["a", "b", "c"].map(|name| {
+            Step::new(
+                name.into(),
+                nonempty!["true".to_string()],
+                "alpine:3".into(),
+                None,
+            )
+        });
+        let pipeline = Pipeline::new(NonEmpty::from_vec(steps.to_vec()).unwrap());
+        let failed = StepResult::StepFailed(ContainerExitCode(2));
+        let mut a = CompletedStep::new("a".into(), StepResult::StepSucceeded);
+        a.attempts = vec![
+            attempt(failed.clone(), 10, 15),
+            attempt(StepResult::StepSucceeded, 25, 30),
+        ];
+        let mut c = CompletedStep::new("c".into(), failed.clone());
+        c.attempts = vec![attempt(failed, 31, 33)];
+        c.attempts[0].oom_killed = true;
+        let b = CompletedStep::new("b".into(), StepResult::StepSkipped);
+        let mut build = Build::new(
+            pipeline,
+            BuildState::BuildFinished(BuildResult::BuildFailed),
+            vec![c, a, b],
+        );
+        build.started_at = Some(at(5));
+        build.finished_at = Some(at(40));
+        build
+    }
+
+    #[test]
+    fn summarises_steps_in_pipeline_order() {
+        let summary = finished_build().summary().unwrap();
+        assert_eq!(summary.result, BuildResult::BuildFailed);
+        assert_eq!((summary.started, summary.finished), (at(5), at(40)));
+        assert_eq!(summary.duration, Duration::from_secs(35));
+        let a = &summary.steps[0];
+        assert_eq!(a.name.0, "a");
+        assert_eq!(a.attempts, 2);
+        // From the start of the first attempt to the end of the last one.
+        assert_eq!((a.started, a.finished), (Some(at(10)), Some(at(30))));
+        assert_eq!(a.duration, Some(Duration::from_secs(20)));
+        let b = &summary.steps[1];
+        assert_eq!((b.name.0.as_str(), b.attempts), ("b", 0));
+        assert_eq!((b.started, b.finished, b.duration), (None, None, None));
+        let c = &summary.steps[2];
+        assert_eq!(c.name.0, "c");
+        assert_eq!(c.duration, Some(Duration::from_secs(2)));
+        assert!(c.oom_killed && !a.oom_killed);
+    }
+
+    #[test]
+    fn displays_results_and_durations() {
+        let summary = finished_build().summary().unwrap();
+        assert_eq!(
+            summary.to_string(),
+            "BuildFailed in 35.0s\n  \
+             a                    StepSucceeded in 20.0s after 2 attempts\n  \
+             b                    StepSkipped\n  \
+             c                    StepFailed(ContainerExitCode(2)) (out of memory) in 2.0s"
+        );
+    }
+
+    #[test]
+    fn only_finished_builds_have_a_summary() {
+        let mut build = finished_build();
+        build.state = BuildState::BuildReady;
+        assert_eq!(build.summary(), None);
+    }
+}
@@ -0,0 +1,16 @@
+//! Fixtures shared by the tests of the core modules.
+
+use nonempty::nonempty;
+
+use super::Step;
+
+/// A step running `true` in `alpine:3` after the steps it `depends_on`.
+pub fn step(name: &str, depends_on: &[&str]) -> Step {
+    let depends_on = depends_on.iter().map(|&d| d.into()).collect();
+    Step::new(
+        name.into(),
+        nonempty!["true".to_string()],
+        "alpine:3".into(),
+        Some(depends_on),
+    )
+}
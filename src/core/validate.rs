@@ -0,0 +1,249 @@
+use std::{collections::HashSet, fmt};
+
+use super::{graph::StepGraph, Pipeline, StepName};
+
+#[derive(Debug, PartialEq, Eq, Clone, thiserror::Error)]
+pub enum ValidationError {
+    /// Error emitted when two steps share the same name.
+    #[error("Step `{}` is defined more than once", .step.0)]
+    DuplicateStep {
+        /// The duplicated step name.
+        step: StepName,
+    },
+    /// Error emitted when a step depends on a step that is not in the pipeline.
+    #[error("Step `{}` depends on unknown step `{}`", .step.0, .dependency.0)]
+    UnknownDependency {
+        /// The step declaring the dependency.
+        step: StepName,
+        /// The missing step.
+        dependency: StepName,
+    },
+    /// Error emitted when steps depend on each other in a loop.
+    #[error("Dependency cycle: {}", display_cycle(.steps))]
+    DependencyCycle {
+        /// The steps forming the cycle, starting and ending with the same step.
+        steps: Vec<StepName>,
+    },
+    /// Error emitted when a step has an empty name.
+    #[error("Step #{} has an empty name", .index + 1)]
+    EmptyStepName {
+        /// Position of the step in the pipeline.
+        index: usize,
+    },
+    /// Error emitted when a step's retry policy allows no attempt at all.
+    #[error("Step `{}` must allow at least one attempt", .step.0)]
+    NoRetryAttempts {
+        /// The step with the retry policy.
+        step: StepName,
+    },
+    /// Error emitted when a step's timeout leaves it no time to run.
+    #[error("Step `{}` must have a timeout of more than zero", .step.0)]
+    ZeroTimeout {
+        /// The step with the timeout.
+        step: StepName,
+    },
+    /// Error emitted when the pipeline's timeout leaves it no time to run.
+    #[error("Pipeline must have a timeout of more than zero")]
+    ZeroPipelineTimeout,
+    /// Error emitted when a step has an empty image.
+    #[error("Step `{}` has an empty image", .step.0)]
+    EmptyImage {
+        /// The step missing an image.
+        step: StepName,
+    },
+}
+
+fn display_cycle(steps: &[StepName]) -> String {
+    steps
+        .iter()
+        .map(|s| format!("`{}`", s.0))
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+/// Every problem found in a pipeline: its own timeout, each step's problems in
+/// the order the steps are declared, then any dependency cycles.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ValidationErrors(pub Vec<ValidationError>);
+
+impl std::error::Error for ValidationErrors {}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pipeline is invalid:")?;
+        for err in &self.0 {
+            write!(f, "\n  - {err}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Pipeline {
+    /// Check the pipeline for problems that would break a build, reporting all
+    /// of them rather than stopping at the first one.
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = vec![];
+        let names: HashSet<&StepName> = self.steps.iter().map(|s| &s.name).collect();
+        let mut seen = HashSet::new();
+        if self.timeout.is_some_and(|t| t.is_zero()) {
+            errors.push(ValidationError::ZeroPipelineTimeout);
+        }
+        for (index, step) in self.steps.iter().enumerate() {
+            if step.name.0.trim().is_empty() {
+                errors.push(ValidationError::EmptyStepName { index });
+            } else if !seen.insert(&step.name) {
+                errors.push(ValidationError::DuplicateStep {
+                    step: step.name.clone(),
+                });
+            }
+            if step.image.0.trim().is_empty() {
+                errors.push(ValidationError::EmptyImage {
+                    step: step.name.clone(),
+                });
+            }
+            if step.retry.as_ref().is_some_and(|r| r.max_attempts == 0) {
+                errors.push(ValidationError::NoRetryAttempts {
+                    step: step.name.clone(),
+                });
+            }
+            if step.timeout.is_some_and(|t| t.is_zero()) {
+                errors.push(ValidationError::ZeroTimeout {
+                    step: step.name.clone(),
+                });
+            }
+            for dependency in step.depends_on.iter().flatten() {
+                if !names.contains(dependency) {
+                    errors.push(ValidationError::UnknownDependency {
+                        step: step.name.clone(),
+                        dependency: dependency.clone(),
+                    });
+                }
+            }
+        }
+        for steps in StepGraph::new(self).cycles() {
+            errors.push(ValidationError::DependencyCycle { steps });
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(errors))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use nonempty::NonEmpty;
+
+    use super::*;
+    use crate::core::{testing::step, Step};
+
+    fn errors(steps: Vec<Step>) -> Vec<ValidationError> {
+        let pipeline = Pipeline::new(NonEmpty::from_vec(steps).unwrap());
+        pipeline.validate().map_or_else(|errs| errs.0, |()| vec![])
+    }
+
+    fn names(names: &[&str]) -> Vec<StepName> {
+        names.iter().map(|&n| n.into()).collect()
+    }
+
+    #[test]
+    fn accepts_a_valid_pipeline() {
+        assert_eq!(errors(vec![step("a", &[]), step("b", &["a"])]), vec![]);
+    }
+
+    #[test]
+    fn reports_duplicate_names() {
+        assert_eq!(
+            errors(vec![step("a", &[]), step("a", &[])]),
+            vec![ValidationError::DuplicateStep { step: "a".into() }]
+        );
+    }
+
+    #[test]
+    fn reports_unknown_dependencies() {
+        assert_eq!(
+            errors(vec![step("a", &["missing"])]),
+            vec![ValidationError::UnknownDependency {
+                step: "a".into(),
+                dependency: "missing".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_self_cycles() {
+        assert_eq!(
+            errors(vec![step("a", &["a"])]),
+            vec![ValidationError::DependencyCycle {
+                steps: names(&["a", "a"])
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_cycles_across_steps() {
+        assert_eq!(
+            errors(vec![
+                step("a", &["c"]),
+                step("b", &["a"]),
+                step("c", &["b"])
+            ]),
+            vec![ValidationError::DependencyCycle {
+                steps: names(&["a", "c", "b", "a"])
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_zero_timeouts() {
+        let mut a = step("a", &[]);
+        a.timeout = Some(Duration::ZERO);
+        let mut b = step("b", &[]);
+        b.timeout = Some(Duration::from_secs(1));
+        let mut pipeline = Pipeline::new(NonEmpty::from_vec(vec![a, b]).unwrap());
+        pipeline.timeout = Some(Duration::ZERO);
+        assert_eq!(
+            pipeline.validate().unwrap_err().0,
+            vec![
+                ValidationError::ZeroPipelineTimeout,
+                ValidationError::ZeroTimeout { step: "a".into() },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_every_problem_in_declaration_order() {
+        let mut no_image = step("b", &["nope"]);
+        no_image.image = "".into();
+        let errs = errors(vec![
+            step("c", &["c"]),
+            no_image,
+            step("", &[]),
+            step("d", &[]),
+            step("d", &[]),
+        ]);
+        assert_eq!(
+            errs,
+            vec![
+                ValidationError::EmptyImage { step: "b".into() },
+                ValidationError::UnknownDependency {
+                    step: "b".into(),
+                    dependency: "nope".into(),
+                },
+                ValidationError::EmptyStepName { index: 2 },
+                ValidationError::DuplicateStep { step: "d".into() },
+                ValidationError::DependencyCycle {
+                    steps: names(&["c", "c"])
+                },
+            ]
+        );
+        let pipeline = Pipeline::new(NonEmpty::from_vec(vec![step("a", &["x"])]).unwrap());
+        assert_eq!(
+            pipeline.validate().unwrap_err().to_string(),
+            "Pipeline is invalid:\n  - Step `a` depends on unknown step `x`"
+        );
+    }
+}
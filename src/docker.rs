@@ -1,12 +1,22 @@
+//! A minimal async client for the Docker Engine API.
+
 use std::cmp;
 use std::env;
 use std::fmt;
+#[cfg(feature = "ssl")]
+use std::fs;
+#[cfg(feature = "ssl")]
+use std::io::BufReader;
+#[cfg(feature = "ssl")]
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 
 use self::errors::Error;
+use self::read::AsyncUpgraded;
 use self::read::JsonLineDecoder;
 use self::read::NewlineLogOutputDecoder;
 use self::read::StreamReader;
@@ -22,23 +32,43 @@ use http::header::CONTENT_TYPE;
 use http::request::Builder;
 use http_body_util::{BodyExt, Full};
 use hyper::body::Incoming;
-use hyper::{self, body::Bytes, Method, Request, Response, StatusCode};
+use hyper::{self, body::Bytes, Request, Response, StatusCode};
+#[cfg(feature = "ssl")]
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
 use hyper_util::client::legacy::Client;
 use hyper_util::rt::TokioExecutor;
 use hyperlocal_next::UnixConnector;
+#[cfg(feature = "ssl")]
+use rustls::ClientConfig;
 use serde::de::DeserializeOwned;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
+use tokio::io::{ReadHalf, WriteHalf};
 use tokio_util::codec::FramedRead;
 
 pub mod container;
 pub mod errors;
+pub mod exec;
+pub mod image;
+#[cfg(test)]
+pub mod mock;
+pub mod network;
 pub mod read;
+pub mod system;
 pub mod uri;
 pub mod utils;
+pub mod volume;
 
 pub const DEFAULT_SOCKET: &str = "unix:///var/run/docker.sock";
 
+/// Address of a Docker daemon listening on TCP without TLS.
+pub const DEFAULT_TCP_ADDRESS: &str = "tcp://localhost:2375";
+
+/// Address of a Docker daemon listening on TCP with TLS.
+#[cfg(feature = "ssl")]
+pub const DEFAULT_SSL_ADDRESS: &str = "tcp://localhost:2376";
+
 pub const DEFAULT_DOCKER_HOST: &str = DEFAULT_SOCKET;
 
 /// Default Client Version to communicate with the server.
@@ -62,6 +92,22 @@ impl fmt::Display for ClientVersion {
     }
 }
 
+/// Parse a `major.minor` version, as the daemon reports it.
+impl FromStr for ClientVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (major, minor) = s.split_once('.').ok_or(Error::APIVersionParseError {})?;
+        match (major.parse(), minor.parse()) {
+            (Ok(major_version), Ok(minor_version)) => Ok(ClientVersion {
+                major_version,
+                minor_version,
+            }),
+            _ => Err(Error::APIVersionParseError {}),
+        }
+    }
+}
+
 impl PartialOrd for ClientVersion {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
         match self.major_version.partial_cmp(&other.major_version) {
@@ -83,18 +129,31 @@ impl From<&(AtomicUsize, AtomicUsize)> for ClientVersion {
 #[derive(Debug, Clone)]
 pub(crate) enum ClientType {
     Unix,
+    Http,
+    #[cfg(feature = "ssl")]
+    Ssl,
 }
 
 pub(crate) enum Transport {
     Unix {
         client: Client<UnixConnector, Full<Bytes>>,
     },
+    Http {
+        client: Client<HttpConnector, Full<Bytes>>,
+    },
+    #[cfg(feature = "ssl")]
+    Https {
+        client: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    },
 }
 
 impl fmt::Debug for Transport {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Transport::Unix { .. } => write!(f, "Unix"),
+            Transport::Http { .. } => write!(f, "HTTP"),
+            #[cfg(feature = "ssl")]
+            Transport::Https { .. } => write!(f, "HTTPS"),
         }
     }
 }
@@ -126,6 +185,21 @@ impl Clone for Docker {
 }
 
 impl Docker {
+    /// Connect to the daemon `DOCKER_HOST` points at, the local socket when it
+    /// is not set. `https://` hosts use TLS, and so do `tcp://` hosts when
+    /// `DOCKER_TLS_VERIFY` is set; either fails without the `ssl` feature
+    /// rather than falling back to plain HTTP.
+    pub fn connect_with_defaults() -> Result<Docker, Error> {
+        let host = env::var("DOCKER_HOST").unwrap_or_else(|_| DEFAULT_DOCKER_HOST.to_string());
+        let tls_verify = env::var("DOCKER_TLS_VERIFY").is_ok_and(|v| !v.is_empty());
+        match client_type_for(&host, tls_verify)? {
+            ClientType::Unix => Docker::connect_with_unix_defaults(),
+            ClientType::Http => Docker::connect_with_http_defaults(),
+            #[cfg(feature = "ssl")]
+            ClientType::Ssl => Docker::connect_with_ssl_defaults(),
+        }
+    }
+
     pub fn connect_with_unix_defaults() -> Result<Docker, Error> {
         let socket_path = env::var("DOCKER_HOST").ok().and_then(|p| {
             if p.starts_with("unix://") {
@@ -160,20 +234,164 @@ impl Docker {
             )),
         })
     }
+
+    /// Connect over plain HTTP to the `tcp://` or `http://` address in
+    /// `DOCKER_HOST`, or [`DEFAULT_TCP_ADDRESS`].
+    pub fn connect_with_http_defaults() -> Result<Docker, Error> {
+        let host = env::var("DOCKER_HOST").unwrap_or_else(|_| DEFAULT_TCP_ADDRESS.to_string());
+        Docker::connect_with_http(&host, DEFAULT_TIMEOUT, API_DEFAULT_VERSION)
+    }
+
+    pub fn connect_with_http(
+        addr: &str,
+        timeout: u64,
+        client_version: &ClientVersion,
+    ) -> Result<Docker, Error> {
+        let client_addr = addr.replacen("tcp://", "", 1).replacen("http://", "", 1);
+        let http_connector = HttpConnector::new();
+        let client_builder = Client::builder(TokioExecutor::new());
+
+        let client = client_builder.build(http_connector);
+        let transport = Transport::Http { client };
+        Ok(Docker {
+            client_addr,
+            client_timeout: timeout,
+            transport: Arc::new(transport),
+            client_type: ClientType::Http,
+            version: Arc::new((
+                AtomicUsize::new(client_version.major_version),
+                AtomicUsize::new(client_version.minor_version),
+            )),
+        })
+    }
+}
+
+/// Directory holding `key.pem`, `cert.pem` and `ca.pem`: `DOCKER_CERT_PATH`, or
+/// `~/.docker` like the Docker CLI.
+#[cfg(feature = "ssl")]
+fn default_cert_path() -> Result<PathBuf, Error> {
+    match env::var("DOCKER_CERT_PATH") {
+        Ok(path) => Ok(PathBuf::from(path)),
+        Err(_) => Ok(home::home_dir()
+            .ok_or(Error::NoHomePathError)?
+            .join(".docker")),
+    }
+}
+
+#[cfg(feature = "ssl")]
+impl Docker {
+    /// Connect over TLS to the address in `DOCKER_HOST`, or
+    /// [`DEFAULT_SSL_ADDRESS`], with the client certificate and CA found in
+    /// [`DOCKER_CERT_PATH`](default_cert_path).
+    pub fn connect_with_ssl_defaults() -> Result<Docker, Error> {
+        let cert_path = default_cert_path()?;
+        let host = env::var("DOCKER_HOST").unwrap_or_else(|_| DEFAULT_SSL_ADDRESS.to_string());
+        Docker::connect_with_ssl(
+            &host,
+            &cert_path.join("key.pem"),
+            &cert_path.join("cert.pem"),
+            &cert_path.join("ca.pem"),
+            DEFAULT_TIMEOUT,
+            API_DEFAULT_VERSION,
+        )
+    }
+
+    /// Connect over TLS, authenticating with `ssl_key` and `ssl_cert` and
+    /// trusting the daemon certificates signed by `ssl_ca` or a system root.
+    pub fn connect_with_ssl(
+        addr: &str,
+        ssl_key: &Path,
+        ssl_cert: &Path,
+        ssl_ca: &Path,
+        timeout: u64,
+        client_version: &ClientVersion,
+    ) -> Result<Docker, Error> {
+        let client_addr = addr.replacen("tcp://", "", 1).replacen("https://", "", 1);
+
+        let mut root_store = rustls::RootCertStore::empty();
+        // A missing or unreadable system store still leaves `ssl_ca`, and a
+        // malformed system certificate is skipped rather than failing.
+        root_store.add_parsable_certificates(
+            rustls_native_certs::load_native_certs().unwrap_or_default(),
+        );
+        for cert in Docker::read_certs(ssl_ca)? {
+            root_store.add(cert)?;
+        }
+        let cert_chain = Docker::read_certs(ssl_cert)?;
+        let key = Docker::read_key(ssl_key)?;
+        let config = ClientConfig::builder()
+            .with_root_certificates(root_store)
+            .with_client_auth_cert(cert_chain, key)?;
+
+        let mut http_connector = HttpConnector::new();
+        http_connector.enforce_http(false);
+        let https_connector = HttpsConnector::from((http_connector, config));
+        let client_builder = Client::builder(TokioExecutor::new());
+
+        let client = client_builder.build(https_connector);
+        let transport = Transport::Https { client };
+        Ok(Docker {
+            client_addr,
+            client_timeout: timeout,
+            transport: Arc::new(transport),
+            client_type: ClientType::Ssl,
+            version: Arc::new((
+                AtomicUsize::new(client_version.major_version),
+                AtomicUsize::new(client_version.minor_version),
+            )),
+        })
+    }
+
+    fn read_certs(path: &Path) -> Result<Vec<rustls_pki_types::CertificateDer<'static>>, Error> {
+        let cert_path_error = || Error::CertPathError {
+            path: path.to_path_buf(),
+        };
+        let file = fs::File::open(path).map_err(|_| cert_path_error())?;
+        rustls_pemfile::certs(&mut BufReader::new(file))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| cert_path_error())
+    }
+
+    fn read_key(path: &Path) -> Result<rustls_pki_types::PrivateKeyDer<'static>, Error> {
+        let file = fs::File::open(path).map_err(|_| Error::CertPathError {
+            path: path.to_path_buf(),
+        })?;
+        let keys = rustls_pemfile::read_all(&mut BufReader::new(file))
+            .filter_map(|item| match item {
+                Ok(rustls_pemfile::Item::Pkcs1Key(key)) => Some(Ok(key.into())),
+                Ok(rustls_pemfile::Item::Pkcs8Key(key)) => Some(Ok(key.into())),
+                Ok(rustls_pemfile::Item::Sec1Key(key)) => Some(Ok(key.into())),
+                Ok(_) => None,
+                Err(_) => Some(Err(Error::CertParseError {
+                    path: path.to_path_buf(),
+                })),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match <[_; 1]>::try_from(keys) {
+            Ok([key]) => Ok(key),
+            Err(keys) => Err(Error::CertMultipleKeys {
+                count: keys.len(),
+                path: path.to_path_buf(),
+            }),
+        }
+    }
 }
 
 impl Docker {
     /// By default, 2 minutes.
+    #[allow(dead_code)]
     pub fn with_timeout(mut self, timeout: Duration) -> Self {
         self.set_timeout(timeout);
         self
     }
 
     /// Get the current timeout.
+    #[allow(dead_code)]
     pub fn timeout(&self) -> Duration {
         Duration::from_secs(self.client_timeout)
     }
 
+    #[allow(dead_code)]
     pub fn set_timeout(&mut self, timeout: Duration) {
         self.client_timeout = timeout.as_secs();
     }
@@ -225,6 +443,20 @@ impl Docker {
         let fut = self.process_request(req);
         async move { Docker::decode_response(fut.await?).await }
     }
+    /// Send a request asking to upgrade the connection, as attach and exec do,
+    /// and split the raw stream the daemon switches to.
+    pub(crate) fn process_upgraded(
+        &self,
+        req: Result<Request<Full<Bytes>>, Error>,
+    ) -> impl Future<Output = Result<(ReadHalf<AsyncUpgraded>, WriteHalf<AsyncUpgraded>), Error>>
+    {
+        let fut = self.process_request(req);
+        async move {
+            let response = fut.await?;
+            let upgraded = hyper::upgrade::on(response).await?;
+            Ok(tokio::io::split(AsyncUpgraded::new(upgraded)))
+        }
+    }
     pub(crate) fn serialize_payload<S>(body: Option<S>) -> Result<Full<Bytes>, Error>
     where
         S: serde::Serialize,
@@ -268,6 +500,36 @@ impl Docker {
         query: Option<O>,
         payload: Result<Full<Bytes>, Error>,
     ) -> Result<Request<Full<Bytes>>, Error>
+    where
+        O: serde::Serialize,
+    {
+        let version = self.client_version();
+        self.build_request_for_version(path, builder, query, payload, Some(&version))
+    }
+
+    /// Build a request without the `/v1.xx` prefix, for the endpoints used
+    /// before the client knows which version the daemon speaks.
+    pub(crate) fn build_unversioned_request<O>(
+        &self,
+        path: &str,
+        builder: Builder,
+        query: Option<O>,
+        payload: Result<Full<Bytes>, Error>,
+    ) -> Result<Request<Full<Bytes>>, Error>
+    where
+        O: serde::Serialize,
+    {
+        self.build_request_for_version(path, builder, query, payload, None)
+    }
+
+    fn build_request_for_version<O>(
+        &self,
+        path: &str,
+        builder: Builder,
+        query: Option<O>,
+        payload: Result<Full<Bytes>, Error>,
+        client_version: Option<&ClientVersion>,
+    ) -> Result<Request<Full<Bytes>>, Error>
     where
         O: serde::Serialize,
     {
@@ -276,7 +538,7 @@ impl Docker {
             &self.client_type,
             path,
             query,
-            &self.client_version(),
+            client_version,
         )?;
         let req_uri: hyper::Uri = uri.try_into()?;
         Ok(builder
@@ -329,6 +591,9 @@ impl Docker {
         // This is where we determine to which transport we issue the request.
         let request = match *transport {
             Transport::Unix { ref client } => client.request(req),
+            Transport::Http { ref client } => client.request(req),
+            #[cfg(feature = "ssl")]
+            Transport::Https { ref client } => client.request(req),
         };
 
         match tokio::time::timeout(Duration::from_secs(timeout), request).await {
@@ -361,3 +626,124 @@ impl Docker {
         })
     }
 }
+
+/// How to reach the daemon at `host`.
+fn client_type_for(host: &str, tls_verify: bool) -> Result<ClientType, Error> {
+    let tls = host.starts_with("https://") || (host.starts_with("tcp://") && tls_verify);
+    match host {
+        h if h.starts_with("unix://") => Ok(ClientType::Unix),
+        #[cfg(feature = "ssl")]
+        _ if tls => Ok(ClientType::Ssl),
+        #[cfg(not(feature = "ssl"))]
+        _ if tls => Err(Error::SslDisabledError {
+            uri: host.to_string(),
+        }),
+        h if h.starts_with("tcp://") || h.starts_with("http://") => Ok(ClientType::Http),
+        _ => Err(Error::UnsupportedURISchemeError {
+            uri: host.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockDocker;
+    use super::*;
+
+    #[tokio::test]
+    async fn connects_over_tcp() {
+        let mock = MockDocker::start_tcp().await;
+        let docker = mock.docker();
+        assert!(matches!(docker.client_type, ClientType::Http));
+        let err = docker.inspect_image("nope").await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::DockerResponseServerError {
+                status_code: 404,
+                ..
+            }
+        ));
+        assert_eq!(mock.request().path, "/images/nope/json");
+    }
+
+    #[test]
+    fn builds_urls_for_each_transport() {
+        let version = Some(API_DEFAULT_VERSION);
+        let uri = |addr, client_type| {
+            let uri = Uri::parse(addr, &client_type, "/info", Some([("a", "b")]), version);
+            hyper::Uri::try_from(uri.unwrap()).unwrap().to_string()
+        };
+        assert_eq!(
+            uri("localhost:2375", ClientType::Http),
+            "http://localhost:2375/v1.42/info?a=b"
+        );
+        assert_eq!(
+            uri("/var/run/docker.sock", ClientType::Unix),
+            format!(
+                "unix://{}/v1.42/info?a=b",
+                hex::encode("/var/run/docker.sock")
+            )
+        );
+        let uri = Uri::parse(
+            "localhost:2375",
+            &ClientType::Http,
+            "/_ping",
+            None::<()>,
+            None,
+        );
+        assert_eq!(
+            hyper::Uri::try_from(uri.unwrap()).unwrap().to_string(),
+            "http://localhost:2375/_ping"
+        );
+    }
+
+    #[test]
+    fn picks_the_transport_from_the_host() {
+        let client_type = |host, tls_verify| match client_type_for(host, tls_verify) {
+            Ok(ClientType::Unix) => "unix".to_string(),
+            Ok(ClientType::Http) => "http".to_string(),
+            #[cfg(feature = "ssl")]
+            Ok(ClientType::Ssl) => "ssl".to_string(),
+            Err(err) => err.to_string(),
+        };
+        assert_eq!(client_type("unix:///var/run/docker.sock", true), "unix");
+        assert_eq!(client_type("tcp://localhost:2375", false), "http");
+        assert_eq!(client_type("http://localhost:2375", true), "http");
+        assert_eq!(
+            client_type("ssh://localhost", false),
+            "URI scheme is not supported: ssh://localhost"
+        );
+        for (host, tls_verify) in [
+            ("tcp://localhost:2376", true),
+            ("https://localhost:2376", false),
+        ] {
+            #[cfg(feature = "ssl")]
+            assert_eq!(client_type(host, tls_verify), "ssl");
+            #[cfg(not(feature = "ssl"))]
+            assert_eq!(
+                client_type(host, tls_verify),
+                format!("TLS is needed to connect to {host}, but the `ssl` feature is not enabled")
+            );
+        }
+    }
+
+    #[cfg(feature = "ssl")]
+    #[test]
+    fn ssl_reports_missing_certificates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name| dir.path().join(name);
+        let err = Docker::connect_with_ssl(
+            "tcp://localhost:2376",
+            &path("key.pem"),
+            &path("cert.pem"),
+            &path("ca.pem"),
+            DEFAULT_TIMEOUT,
+            API_DEFAULT_VERSION,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(&err, Error::CertPathError { path: p } if *p == path("ca.pem")),
+            "{err:?}"
+        );
+    }
+}
@@ -1,18 +1,22 @@
-use std::fmt::format;
-use std::{collections::HashMap, hash::Hash};
+use std::{collections::HashMap, fmt, hash::Hash, pin::Pin};
 
 use derive_new::new;
 use futures_core::Stream;
-use futures_util::StreamExt;
+use futures_util::{StreamExt, TryStreamExt};
+use http::header::{CONNECTION, UPGRADE};
 use http::request::Builder;
 use http::Method;
 use http_body_util::Full;
 use hyper::body::Bytes;
 use serde_derive::{Deserialize, Serialize};
+use tokio::io::AsyncWrite;
+use tokio_util::codec::FramedRead;
 
 use bollard_stubs::models::*;
 
 use super::errors::Error;
+use super::read::NewlineLogOutputDecoder;
+use super::utils::LogOutput;
 use super::Docker;
 
 #[derive(Debug, Clone, Default, PartialEq, Serialize, new)]
@@ -37,6 +41,32 @@ pub struct CreateContainerConfig<T> {
     pub entry_point: Vec<T>,
     #[serde(rename = "Cmd")]
     pub cmd: T,
+    /// A list of environment variables to set inside the container in the form `["VAR=value",
+    /// ...]`.
+    #[serde(rename = "Env", skip_serializing_if = "Option::is_none")]
+    #[new(default)]
+    pub env: Option<Vec<T>>,
+    /// The working directory for commands to run in.
+    #[serde(rename = "WorkingDir", skip_serializing_if = "Option::is_none")]
+    #[new(default)]
+    pub working_dir: Option<T>,
+    /// Container configuration that depends on the host we are running on: mounts, binds,
+    /// resources, networking.
+    #[serde(rename = "HostConfig", skip_serializing_if = "Option::is_none")]
+    #[new(default)]
+    pub host_config: Option<HostConfig>,
+    /// Open `stdin`, so that it can be attached to.
+    #[serde(rename = "OpenStdin", skip_serializing_if = "std::ops::Not::not")]
+    #[new(default)]
+    pub open_stdin: bool,
+    /// Attach to `stdin` when the container starts.
+    #[serde(rename = "AttachStdin", skip_serializing_if = "std::ops::Not::not")]
+    #[new(default)]
+    pub attach_stdin: bool,
+    /// Close `stdin` after the attached client disconnects.
+    #[serde(rename = "StdinOnce", skip_serializing_if = "std::ops::Not::not")]
+    #[new(default)]
+    pub stdin_once: bool,
 }
 
 #[derive(Debug, Clone, Default, PartialEq, Serialize, new)]
@@ -58,6 +88,122 @@ where
     pub condition: T,
 }
 
+#[derive(Debug, Clone, Default, PartialEq, Serialize, new)]
+pub struct LogsOptions<T>
+where
+    T: Into<String> + serde::Serialize,
+{
+    /// Return the logs as a finite stream (false) or keep the connection open and stream new
+    /// output as it is produced (true).
+    pub follow: bool,
+    /// Return logs from `stdout`.
+    pub stdout: bool,
+    /// Return logs from `stderr`.
+    pub stderr: bool,
+    /// Only return logs since this time, as a UNIX timestamp.
+    pub since: i64,
+    /// Only return logs before this time, as a UNIX timestamp.
+    pub until: i64,
+    /// Add timestamps to every log line.
+    pub timestamps: bool,
+    /// Only return this number of log lines from the end of the logs. Specify as an integer or
+    /// `all` to output all log lines.
+    pub tail: T,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, new)]
+pub struct RemoveContainerOptions {
+    /// Remove the anonymous volumes associated with the container.
+    pub v: bool,
+    /// If the container is running, kill it before removing it.
+    pub force: bool,
+    /// Remove the specified link associated with the container.
+    pub link: bool,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, new)]
+#[allow(dead_code)]
+pub struct StopContainerOptions {
+    /// Number of seconds to wait before killing the container
+    pub t: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, new)]
+pub struct KillContainerOptions<T>
+where
+    T: Into<String> + serde::Serialize,
+{
+    /// Signal to send to the container as an integer or string (e.g. `SIGINT`)
+    pub signal: T,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, new)]
+pub struct InspectContainerOptions {
+    /// Return the size of container as fields `SizeRw` and `SizeRootFs`
+    pub size: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, new)]
+pub struct ListContainersOptions<T>
+where
+    T: Into<String> + Eq + Hash + serde::Serialize,
+{
+    /// Return all containers. By default, only running containers are shown
+    pub all: bool,
+    /// Return this number of most recently created containers, including non-running ones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[new(default)]
+    pub limit: Option<isize>,
+    /// Return the size of container as fields `SizeRw` and `SizeRootFs`
+    #[new(default)]
+    pub size: bool,
+    /// Filters to process on the container list, encoded as JSON. Available filters include:
+    ///  - `label=key` or `label="key=value"` of a container label
+    ///  - `name=<name>` a container's name
+    ///  - `status=`(`created`|`restarting`|`running`|`removing`|`paused`|`exited`|`dead`)
+    ///  - `id=<ID>` a container's ID
+    ///  - `exited=<int>` containers with exit code of `<int>`
+    #[serde(serialize_with = "crate::docker::utils::serialize_as_json")]
+    pub filters: HashMap<T, Vec<T>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, new)]
+#[serde(rename_all = "camelCase")]
+pub struct AttachContainerOptions<T>
+where
+    T: Into<String> + serde::Serialize,
+{
+    /// Attach to `stdin`.
+    pub stdin: bool,
+    /// Attach to `stdout`.
+    pub stdout: bool,
+    /// Attach to `stderr`.
+    pub stderr: bool,
+    /// Stream output as it is produced.
+    pub stream: bool,
+    /// Replay the output written before attaching.
+    pub logs: bool,
+    /// Override the key sequence for detaching a container.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[new(default)]
+    pub detach_keys: Option<T>,
+}
+
+/// The two halves of a connection attached to a container.
+pub struct AttachContainerResults {
+    #[allow(dead_code)] // Steps are only fed input; their output is followed as logs.
+    pub output: Pin<Box<dyn Stream<Item = Result<LogOutput, Error>> + Send>>,
+    /// The container's `stdin`, when attached with `stdin` and the container
+    /// was created with `OpenStdin`.
+    pub input: Pin<Box<dyn AsyncWrite + Send>>,
+}
+
+impl fmt::Debug for AttachContainerResults {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AttachContainerResults")
+    }
+}
+
 impl Docker {
     pub async fn create_container<T, Z>(
         &self,
@@ -78,6 +224,39 @@ impl Docker {
         self.process_into_value(req).await
     }
 
+    /// Low-level information on a container: its state (exit code, whether it
+    /// was OOM killed, start and finish times), configuration and mounts.
+    pub async fn inspect_container(
+        &self,
+        container_name_or_id: &str,
+        options: Option<InspectContainerOptions>,
+    ) -> Result<ContainerInspectResponse, Error> {
+        let path = format!("/containers/{container_name_or_id}/json");
+        let req = self.build_request(
+            &path,
+            Builder::new().method(Method::GET),
+            options,
+            Ok(Full::new(Bytes::new())),
+        );
+        self.process_into_value(req).await
+    }
+
+    pub async fn list_containers<T>(
+        &self,
+        options: Option<ListContainersOptions<T>>,
+    ) -> Result<Vec<ContainerSummary>, Error>
+    where
+        T: Into<String> + Eq + Hash + serde::Serialize,
+    {
+        let req = self.build_request(
+            "/containers/json",
+            Builder::new().method(Method::GET),
+            options,
+            Ok(Full::new(Bytes::new())),
+        );
+        self.process_into_value(req).await
+    }
+
     pub async fn start_container<T>(
         &self,
         container_name_or_id: &str,
@@ -130,4 +309,438 @@ impl Docker {
             v => v,
         })
     }
+
+    pub fn logs<T>(
+        &self,
+        container_name_or_id: &str,
+        options: Option<LogsOptions<T>>,
+    ) -> impl Stream<Item = Result<LogOutput, Error>>
+    where
+        T: Into<String> + serde::Serialize,
+    {
+        let path = format!("/containers/{container_name_or_id}/logs");
+        let req = self.build_request(
+            &path,
+            Builder::new().method(Method::GET),
+            options,
+            Ok(Full::new(Bytes::new())),
+        );
+
+        self.process_into_stream_string(req)
+    }
+
+    /// Attach to a container to follow its output live and write to its
+    /// `stdin`. Attaching before starting the container catches all of its
+    /// output.
+    pub async fn attach_container<T>(
+        &self,
+        container_name_or_id: &str,
+        options: Option<AttachContainerOptions<T>>,
+    ) -> Result<AttachContainerResults, Error>
+    where
+        T: Into<String> + serde::Serialize,
+    {
+        let path = format!("/containers/{container_name_or_id}/attach");
+        let req = self.build_request(
+            &path,
+            Builder::new()
+                .method(Method::POST)
+                .header(CONNECTION, "Upgrade")
+                .header(UPGRADE, "tcp"),
+            options,
+            Ok(Full::new(Bytes::new())),
+        );
+        let (read, write) = self.process_upgraded(req).await?;
+        let output = FramedRead::new(read, NewlineLogOutputDecoder::new(true)).map_err(Error::from);
+        Ok(AttachContainerResults {
+            output: Box::pin(output),
+            input: Box::pin(write),
+        })
+    }
+
+    #[allow(dead_code)]
+    pub async fn stop_container(
+        &self,
+        container_name_or_id: &str,
+        options: Option<StopContainerOptions>,
+    ) -> Result<(), Error> {
+        let path = format!("/containers/{container_name_or_id}/stop");
+        let req = self.build_request(
+            &path,
+            Builder::new().method(Method::POST),
+            options,
+            Ok(Full::new(Bytes::new())),
+        );
+        self.process_into_unit(req).await
+    }
+
+    pub async fn kill_container<T>(
+        &self,
+        container_name_or_id: &str,
+        options: Option<KillContainerOptions<T>>,
+    ) -> Result<(), Error>
+    where
+        T: Into<String> + serde::Serialize,
+    {
+        let path = format!("/containers/{container_name_or_id}/kill");
+        let req = self.build_request(
+            &path,
+            Builder::new().method(Method::POST),
+            options,
+            Ok(Full::new(Bytes::new())),
+        );
+        self.process_into_unit(req).await
+    }
+
+    pub async fn remove_container(
+        &self,
+        container_name_or_id: &str,
+        options: Option<RemoveContainerOptions>,
+    ) -> Result<(), Error> {
+        let path = format!("/containers/{container_name_or_id}");
+        let req = self.build_request(
+            &path,
+            Builder::new().method(Method::DELETE),
+            options,
+            Ok(Full::new(Bytes::new())),
+        );
+        self.process_into_unit(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+    use serde_json::json;
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::docker::mock::{frame, MockDocker, MockResponse};
+
+    fn config() -> CreateContainerConfig<&'static str> {
+        let mut config = CreateContainerConfig::new(
+            "alpine:3",
+            false,
+            HashMap::from([("nova".to_string(), "")]),
+            vec!["/bin/sh", "-c"],
+            "true",
+        );
+        config.working_dir = Some("/workspace");
+        config
+    }
+
+    #[tokio::test]
+    async fn create_container_sends_name_and_config() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::POST,
+            "/containers/create",
+            MockResponse::json(StatusCode::CREATED, json!({ "Id": "abc", "Warnings": [] })),
+        );
+        let options = CreateContainerOptions::new("nova-1-build", None);
+        let res = mock
+            .docker()
+            .create_container(Some(options), config())
+            .await
+            .unwrap();
+        assert_eq!(res.id, "abc");
+        let req = mock.request();
+        assert_eq!(req.query.as_deref(), Some("name=nova-1-build"));
+        assert_eq!(
+            req.json(),
+            json!({
+                "Image": "alpine:3",
+                "Tty": false,
+                "Labels": { "nova": "" },
+                "Entrypoint": ["/bin/sh", "-c"],
+                "Cmd": "true",
+                "WorkingDir": "/workspace",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn create_container_reports_server_errors() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::POST,
+            "/containers/create",
+            MockResponse::error(StatusCode::CONFLICT, "name already in use"),
+        );
+        let err = mock
+            .docker()
+            .create_container(None::<CreateContainerOptions<String>>, config())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                Error::DockerResponseServerError { status_code: 409, message }
+                    if message == "name already in use"
+            ),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn start_container_accepts_not_modified() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::POST,
+            "/containers/abc/start",
+            MockResponse::empty(StatusCode::NO_CONTENT),
+        )
+        .on(
+            Method::POST,
+            "/containers/def/start",
+            MockResponse::empty(StatusCode::NOT_MODIFIED),
+        );
+        let docker = mock.docker();
+        for id in ["abc", "def"] {
+            docker
+                .start_container(id, None::<StartContainerOptions<String>>)
+                .await
+                .unwrap();
+        }
+        let err = docker
+            .start_container("missing", None::<StartContainerOptions<String>>)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::DockerResponseServerError {
+                status_code: 404,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn wait_container_turns_non_zero_exits_into_errors() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::POST,
+            "/containers/ok/wait",
+            MockResponse::json(StatusCode::OK, json!({ "StatusCode": 0 })),
+        )
+        .on(
+            Method::POST,
+            "/containers/failed/wait",
+            MockResponse::json(
+                StatusCode::OK,
+                json!({ "StatusCode": 3, "Error": { "Message": "boom" } }),
+            ),
+        );
+        let docker = mock.docker();
+        let options = Some(WaitContainerOptions::new("not-running"));
+        let ok: Vec<_> = docker
+            .wait_container("ok", options.clone())
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(ok[0].status_code, 0);
+        let err = Box::pin(docker.wait_container("failed", options))
+            .next()
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::DockerContainerWaitError { error, code: 3 } if error == "boom"),
+            "{err:?}"
+        );
+        assert_eq!(
+            mock.requests()[0].query.as_deref(),
+            Some("condition=not-running")
+        );
+    }
+
+    #[tokio::test]
+    async fn logs_demultiplexes_frames_split_across_chunks() {
+        let mut stream = frame(1, "out\n").to_vec();
+        stream.extend_from_slice(&frame(2, "err\n"));
+        // Split inside the second header and inside the first payload.
+        let chunks = [&stream[..10], &stream[10..14], &stream[14..]].map(|c| c.to_vec());
+        let mock = MockDocker::start();
+        mock.on(
+            Method::GET,
+            "/containers/abc/logs",
+            MockResponse::chunks(StatusCode::OK, chunks),
+        );
+        let options = LogsOptions::new(false, true, true, 0, 0, false, "all");
+        let logs: Vec<_> = mock
+            .docker()
+            .logs("abc", Some(options))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            logs,
+            vec![
+                LogOutput::StdOut {
+                    message: Bytes::from("out\n")
+                },
+                LogOutput::StdErr {
+                    message: Bytes::from("err\n")
+                },
+            ]
+        );
+        assert_eq!(
+            mock.request().query_pairs(),
+            [
+                ("follow", "false"),
+                ("stdout", "true"),
+                ("stderr", "true"),
+                ("since", "0"),
+                ("until", "0"),
+                ("timestamps", "false"),
+                ("tail", "all"),
+            ]
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn logs_splits_tty_output_into_lines() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::GET,
+            "/containers/abc/logs",
+            MockResponse::chunks(StatusCode::OK, ["hel", "lo\nwor", "ld\n"]),
+        );
+        let logs: Vec<_> = mock
+            .docker()
+            .logs("abc", None::<LogsOptions<String>>)
+            .try_collect()
+            .await
+            .unwrap();
+        let lines: Vec<_> = logs.iter().map(ToString::to_string).collect();
+        assert_eq!(lines, vec!["hello\n", "world\n"]);
+        assert!(matches!(logs[0], LogOutput::Console { .. }));
+    }
+
+    #[tokio::test]
+    async fn kill_and_remove_send_their_options() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::POST,
+            "/containers/abc/kill",
+            MockResponse::empty(StatusCode::NO_CONTENT),
+        )
+        .on(
+            Method::DELETE,
+            "/containers/abc",
+            MockResponse::empty(StatusCode::NO_CONTENT),
+        );
+        let docker = mock.docker();
+        docker
+            .kill_container("abc", Some(KillContainerOptions::new("SIGKILL")))
+            .await
+            .unwrap();
+        docker
+            .remove_container("abc", Some(RemoveContainerOptions::new(true, true, false)))
+            .await
+            .unwrap();
+        let queries: Vec<_> = mock.requests().into_iter().map(|r| r.query).collect();
+        assert_eq!(
+            queries,
+            vec![
+                Some("signal=SIGKILL".to_string()),
+                Some("v=true&force=true&link=false".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn attach_container_streams_output_and_input() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::POST,
+            "/containers/abc/attach",
+            MockResponse::upgrade([frame(1, "started\n")]),
+        );
+        let options = AttachContainerOptions::<String>::new(true, true, true, true, false);
+        let AttachContainerResults {
+            mut output,
+            mut input,
+        } = mock
+            .docker()
+            .attach_container("abc", Some(options))
+            .await
+            .unwrap();
+        let next = output.next().await.unwrap().unwrap();
+        assert_eq!(
+            next,
+            LogOutput::StdOut {
+                message: Bytes::from("started\n")
+            }
+        );
+
+        // The mock echoes input back.
+        input.write_all(b"yes\n").await.unwrap();
+        assert_eq!(output.next().await.unwrap().unwrap().to_string(), "yes\n");
+        input.shutdown().await.unwrap();
+        assert!(output.next().await.is_none());
+        assert_eq!(
+            mock.request().query.as_deref(),
+            Some("stdin=true&stdout=true&stderr=true&stream=true&logs=false")
+        );
+    }
+
+    #[tokio::test]
+    async fn inspect_container_reports_state() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::GET,
+            "/containers/abc/json",
+            MockResponse::json(
+                StatusCode::OK,
+                json!({
+                    "Id": "abc",
+                    "State": {
+                        "Status": "exited",
+                        "ExitCode": 137,
+                        "OOMKilled": true,
+                        "StartedAt": "2024-03-01T10:00:00Z",
+                        "FinishedAt": "2024-03-01T10:00:05Z",
+                    },
+                }),
+            ),
+        );
+        let container = mock
+            .docker()
+            .inspect_container("abc", Some(InspectContainerOptions::new(true)))
+            .await
+            .unwrap();
+        let state = container.state.unwrap();
+        assert_eq!(state.exit_code, Some(137));
+        assert_eq!(state.oom_killed, Some(true));
+        assert_eq!(state.finished_at.as_deref(), Some("2024-03-01T10:00:05Z"));
+        assert_eq!(mock.request().query.as_deref(), Some("size=true"));
+    }
+
+    #[tokio::test]
+    async fn list_containers_encodes_filters_as_json() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::GET,
+            "/containers/json",
+            MockResponse::json(
+                StatusCode::OK,
+                json!([{ "Id": "abc", "Names": ["/nova-1-build"], "State": "exited" }]),
+            ),
+        );
+        let filters = HashMap::from([("label", vec!["nova"]), ("status", vec!["exited"])]);
+        let options = ListContainersOptions::new(true, filters);
+        let containers = mock.docker().list_containers(Some(options)).await.unwrap();
+        assert_eq!(containers[0].id.as_deref(), Some("abc"));
+
+        let query: HashMap<_, _> = mock.request().query_pairs().into_iter().collect();
+        assert_eq!(query["all"], "true");
+        assert_eq!(query["size"], "false");
+        assert!(!query.contains_key("limit"));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&query["filters"]).unwrap(),
+            json!({ "label": ["nova"], "status": ["exited"] })
+        );
+    }
 }
@@ -1,4 +1,8 @@
+#[cfg(feature = "ssl")]
+use std::path::PathBuf;
+
 #[derive(Debug, thiserror::Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     /// Error emitted during client instantiation when the `DOCKER_CERT_PATH` environment variable
     /// is invalid.
@@ -55,7 +59,7 @@ pub enum Error {
         /// Character sequence at error location.
         column: usize,
     },
-    /// Error emitted when the docker is requested to build with buildkit without a session id
+    /// Error emitted when the daemon reports an API version that is not `major.minor`
     #[error("Failed to parse API version")]
     APIVersionParseError {},
     /// Error emitted when a request times out.
@@ -77,9 +81,11 @@ pub enum Error {
     },
     /// Error emitted when a session is not provided to the buildkit engine
     #[error("Buildkit requires a unique session")]
+    #[allow(dead_code)]
     MissingSessionBuildkitError {},
     /// Error emitted when a session is not provided to the buildkit engine
     #[error("Buildkit requires a builder version set")]
+    #[allow(dead_code)]
     MissingVersionBuildkitError {},
     /// Error emitted when JSON fails to serialize.
     #[error(transparent)]
@@ -151,6 +157,13 @@ pub enum Error {
         #[from]
         err: hyper_util::client::legacy::Error,
     },
+    /// Error emitted when connecting to a daemon that requires TLS without the `ssl` feature
+    #[cfg(not(feature = "ssl"))]
+    #[error("TLS is needed to connect to {uri}, but the `ssl` feature is not enabled")]
+    SslDisabledError {
+        /// The URI that was attempted to be connected to
+        uri: String,
+    },
     /// Error emitted when connecting to a URI with an unsupported scheme
     #[error("URI scheme is not supported: {uri}")]
     UnsupportedURISchemeError {
@@ -0,0 +1,269 @@
+use std::pin::Pin;
+
+use derive_new::new;
+use futures_core::Stream;
+use futures_util::TryStreamExt;
+use http::header::{CONNECTION, UPGRADE};
+use http::request::Builder;
+use http::Method;
+use http_body_util::Full;
+use hyper::body::Bytes;
+use serde_derive::Serialize;
+use tokio::io::AsyncWrite;
+use tokio_util::codec::FramedRead;
+
+use bollard_stubs::models::*;
+
+use super::errors::Error;
+use super::read::NewlineLogOutputDecoder;
+use super::utils::LogOutput;
+use super::Docker;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, new)]
+#[serde(rename_all = "PascalCase")]
+#[allow(dead_code)]
+pub struct CreateExecOptions<T>
+where
+    T: Into<String> + serde::Serialize,
+{
+    /// Command to run, as a string or array of strings.
+    pub cmd: Vec<T>,
+    /// Attach to `stdin` of the exec command.
+    #[new(default)]
+    pub attach_stdin: bool,
+    /// Attach to `stdout` of the exec command.
+    #[new(default)]
+    pub attach_stdout: bool,
+    /// Attach to `stderr` of the exec command.
+    #[new(default)]
+    pub attach_stderr: bool,
+    /// Allocate a pseudo-TTY.
+    #[new(default)]
+    pub tty: bool,
+    /// A list of environment variables in the form `["VAR=value", ...]`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[new(default)]
+    pub env: Option<Vec<T>>,
+    /// The working directory for the exec process inside the container.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[new(default)]
+    pub working_dir: Option<T>,
+    /// The user, and optionally, group to run the exec process inside the container. Format is
+    /// one of: `user`, `user:group`, `uid`, or `uid:gid`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[new(default)]
+    pub user: Option<T>,
+    /// Runs the exec process with extended privileges.
+    #[new(default)]
+    pub privileged: bool,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, new)]
+#[serde(rename_all = "PascalCase")]
+#[allow(dead_code)]
+pub struct StartExecOptions {
+    /// Return once the command has started instead of streaming its output.
+    pub detach: bool,
+    /// Allocate a pseudo-TTY.
+    pub tty: bool,
+}
+
+/// How a started exec command can be followed.
+#[allow(dead_code)]
+pub enum StartExecResults {
+    /// The command's output, and its `stdin` when it was created with
+    /// `attach_stdin`.
+    Attached {
+        output: Pin<Box<dyn Stream<Item = Result<LogOutput, Error>> + Send>>,
+        input: Pin<Box<dyn AsyncWrite + Send>>,
+    },
+    Detached,
+}
+
+impl std::fmt::Debug for StartExecResults {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StartExecResults::Attached { .. } => write!(f, "StartExecResults::Attached"),
+            StartExecResults::Detached => write!(f, "StartExecResults::Detached"),
+        }
+    }
+}
+
+impl Docker {
+    /// Set up a command to run inside a running container; it is not started
+    /// until [`Docker::start_exec`] is called with the returned id.
+    #[allow(dead_code)]
+    pub async fn create_exec<T>(
+        &self,
+        container_name_or_id: &str,
+        options: CreateExecOptions<T>,
+    ) -> Result<IdResponse, Error>
+    where
+        T: Into<String> + serde::Serialize,
+    {
+        let path = format!("/containers/{container_name_or_id}/exec");
+        let req = self.build_request(
+            &path,
+            Builder::new().method(Method::POST),
+            None::<String>,
+            Docker::serialize_payload(Some(options)),
+        );
+        self.process_into_value(req).await
+    }
+
+    /// Start an exec command. Unless detached, the connection is upgraded to
+    /// a raw stream carrying the command's output and input.
+    #[allow(dead_code)]
+    pub async fn start_exec(
+        &self,
+        exec_id: &str,
+        options: Option<StartExecOptions>,
+    ) -> Result<StartExecResults, Error> {
+        let path = format!("/exec/{exec_id}/start");
+        let options = options.unwrap_or_default();
+        match options.detach {
+            true => {
+                let req = self.build_request(
+                    &path,
+                    Builder::new().method(Method::POST),
+                    None::<String>,
+                    Docker::serialize_payload(Some(options)),
+                );
+                self.process_into_unit(req).await?;
+                Ok(StartExecResults::Detached)
+            }
+            false => {
+                let req = self.build_request(
+                    &path,
+                    Builder::new()
+                        .method(Method::POST)
+                        .header(CONNECTION, "Upgrade")
+                        .header(UPGRADE, "tcp"),
+                    None::<String>,
+                    Docker::serialize_payload(Some(options)),
+                );
+                let (read, write) = self.process_upgraded(req).await?;
+                let output =
+                    FramedRead::new(read, NewlineLogOutputDecoder::new(true)).map_err(Error::from);
+                Ok(StartExecResults::Attached {
+                    output: Box::pin(output),
+                    input: Box::pin(write),
+                })
+            }
+        }
+    }
+
+    /// State of an exec command, including its exit code once it is done.
+    #[allow(dead_code)]
+    pub async fn inspect_exec(&self, exec_id: &str) -> Result<ExecInspectResponse, Error> {
+        let path = format!("/exec/{exec_id}/json");
+        let req = self.build_request(
+            &path,
+            Builder::new().method(Method::GET),
+            None::<String>,
+            Ok(Full::new(Bytes::new())),
+        );
+        self.process_into_value(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+    use http::StatusCode;
+    use serde_json::json;
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::docker::mock::{frame, MockDocker, MockResponse};
+
+    #[tokio::test]
+    async fn create_exec_sends_command() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::POST,
+            "/containers/abc/exec",
+            MockResponse::json(StatusCode::CREATED, json!({ "Id": "e1" })),
+        );
+        let mut options = CreateExecOptions::new(vec!["ls", "-l"]);
+        options.attach_stdout = true;
+        options.working_dir = Some("/workspace");
+        let exec = mock.docker().create_exec("abc", options).await.unwrap();
+        assert_eq!(exec.id, "e1");
+        assert_eq!(
+            mock.request().json(),
+            json!({
+                "Cmd": ["ls", "-l"],
+                "AttachStdin": false,
+                "AttachStdout": true,
+                "AttachStderr": false,
+                "Tty": false,
+                "WorkingDir": "/workspace",
+                "Privileged": false,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn start_exec_detached() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::POST,
+            "/exec/e1/start",
+            MockResponse::empty(StatusCode::OK),
+        );
+        let res = mock
+            .docker()
+            .start_exec("e1", Some(StartExecOptions::new(true, false)))
+            .await
+            .unwrap();
+        assert!(matches!(res, StartExecResults::Detached));
+        assert_eq!(
+            mock.request().json(),
+            json!({ "Detach": true, "Tty": false })
+        );
+    }
+
+    #[tokio::test]
+    async fn start_exec_attached_streams_output_and_input() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::POST,
+            "/exec/e1/start",
+            MockResponse::upgrade([frame(1, "out\n"), frame(2, "err\n")]),
+        );
+        let res = mock.docker().start_exec("e1", None).await.unwrap();
+        let StartExecResults::Attached {
+            mut output,
+            mut input,
+        } = res
+        else {
+            panic!("{res:?}");
+        };
+        let line = |line: Option<Result<LogOutput, Error>>| line.unwrap().unwrap().to_string();
+        assert_eq!(line(output.next().await), "out\n");
+        assert_eq!(line(output.next().await), "err\n");
+
+        // The mock echoes input back, unframed as a TTY would.
+        input.write_all(b"hello\n").await.unwrap();
+        assert_eq!(line(output.next().await), "hello\n");
+        input.shutdown().await.unwrap();
+        assert!(output.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn inspect_exec_reports_exit_code() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::GET,
+            "/exec/e1/json",
+            MockResponse::json(
+                StatusCode::OK,
+                json!({ "ID": "e1", "Running": false, "ExitCode": 2 }),
+            ),
+        );
+        let exec = mock.docker().inspect_exec("e1").await.unwrap();
+        assert_eq!(exec.exit_code, Some(2));
+        assert_eq!(exec.running, Some(false));
+    }
+}
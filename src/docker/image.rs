@@ -0,0 +1,169 @@
+use derive_new::new;
+use futures_core::Stream;
+use futures_util::StreamExt;
+use http::request::Builder;
+use http::Method;
+use http_body_util::Full;
+use hyper::body::Bytes;
+use serde_derive::Serialize;
+
+use bollard_stubs::models::*;
+
+use super::errors::Error;
+use super::Docker;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, new)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateImageOptions<T>
+where
+    T: Into<String> + serde::Serialize,
+{
+    /// Name of the image to pull. The name may include a tag or digest.
+    pub from_image: T,
+    /// Tag or digest. If empty when pulling an image, this causes all tags for the given image to
+    /// be pulled.
+    pub tag: T,
+    /// Platform in the format `os[/arch[/variant]]`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<T>,
+}
+
+impl Docker {
+    /// Pull an image from a registry, streaming the progress the daemon reports.
+    pub fn create_image<T>(
+        &self,
+        options: Option<CreateImageOptions<T>>,
+    ) -> impl Stream<Item = Result<CreateImageInfo, Error>>
+    where
+        T: Into<String> + serde::Serialize,
+    {
+        let url = "/images/create";
+        let req = self.build_request(
+            url,
+            Builder::new().method(Method::POST),
+            options,
+            Ok(Full::new(Bytes::new())),
+        );
+
+        self.process_into_stream(req).map(|res| match res {
+            Ok(CreateImageInfo {
+                error: Some(error), ..
+            }) => Err(Error::DockerStreamError { error }),
+            v => v,
+        })
+    }
+
+    pub async fn inspect_image(&self, image_name: &str) -> Result<Image, Error> {
+        let path = format!("/images/{image_name}/json");
+        let req = self.build_request(
+            &path,
+            Builder::new().method(Method::GET),
+            None::<String>,
+            Ok(Full::new(Bytes::new())),
+        );
+        self.process_into_value(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::TryStreamExt;
+    use http::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::docker::mock::{MockDocker, MockResponse};
+
+    #[tokio::test]
+    async fn create_image_streams_progress() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::POST,
+            "/images/create",
+            MockResponse::chunks(
+                StatusCode::OK,
+                [
+                    "{\"status\":\"Pulling from library/alpine\",\"id\":\"3\"}\n{\"stat",
+                    "us\":\"Downloading\",\"progress\":\"[=>  ]\",\"id\":\"f1\"}\n",
+                    "{\"status\":\"Status: Downloaded newer image for alpine:3\"}\n",
+                ],
+            ),
+        );
+        let options = CreateImageOptions::new("alpine", "3", None);
+        let info: Vec<_> = mock
+            .docker()
+            .create_image(Some(options))
+            .try_collect()
+            .await
+            .unwrap();
+        let status: Vec<_> = info.iter().filter_map(|i| i.status.as_deref()).collect();
+        assert_eq!(
+            status,
+            vec![
+                "Pulling from library/alpine",
+                "Downloading",
+                "Status: Downloaded newer image for alpine:3"
+            ]
+        );
+        assert_eq!(info[1].progress.as_deref(), Some("[=>  ]"));
+        assert_eq!(
+            mock.request().query.as_deref(),
+            Some("fromImage=alpine&tag=3")
+        );
+    }
+
+    #[tokio::test]
+    async fn create_image_fails_on_stream_errors() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::POST,
+            "/images/create",
+            MockResponse::json_lines(
+                StatusCode::OK,
+                vec![
+                    json!({ "status": "Pulling from library/nope" }),
+                    json!({ "error": "manifest unknown" }),
+                ],
+            ),
+        );
+        let options = CreateImageOptions::new("nope", "latest", None);
+        let err = mock
+            .docker()
+            .create_image(Some(options))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::DockerStreamError { error } if error == "manifest unknown"),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn inspect_image_reports_missing_images() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::GET,
+            "/images/alpine:3/json",
+            MockResponse::json(
+                StatusCode::OK,
+                serde_json::to_value(Image {
+                    id: "sha256:abc".to_string(),
+                    ..Default::default()
+                })
+                .unwrap(),
+            ),
+        );
+        let docker = mock.docker();
+        let image = docker.inspect_image("alpine:3").await.unwrap();
+        assert_eq!(image.id, "sha256:abc");
+        let err = docker.inspect_image("nope").await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::DockerResponseServerError {
+                status_code: 404,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,320 @@
+//! A stand-in Docker Engine listening on a temporary Unix socket, so the client
+//! can be exercised end to end without a daemon.
+//!
+//! Responses are registered per method and path (without the `/v1.xx`
+//! version prefix). Requests without a matching response get the 404 the
+//! Engine sends for unknown routes, and every request is recorded.
+//! Upgraded connections, as used by attach and exec, write their chunks on the
+//! raw stream and then echo back whatever the client sends.
+
+use std::{
+    convert::Infallible,
+    sync::{Arc, Mutex},
+};
+
+use futures_util::stream;
+use http::{
+    header::{CONNECTION, UPGRADE},
+    Method, StatusCode,
+};
+use http_body_util::{combinators::BoxBody, BodyExt, Empty, StreamBody};
+use hyper::{
+    body::{Bytes, Frame, Incoming},
+    server::conn::http1,
+    service::service_fn,
+    upgrade::OnUpgrade,
+    Request, Response,
+};
+use hyper_util::rt::TokioIo;
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, UnixListener},
+    task::JoinHandle,
+};
+
+use super::{Docker, API_DEFAULT_VERSION};
+
+/// A canned response, sent as one chunk per entry of `chunks`.
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: StatusCode,
+    chunks: Vec<Bytes>,
+    upgrade: bool,
+}
+
+impl MockResponse {
+    pub fn empty(status: StatusCode) -> Self {
+        MockResponse {
+            status,
+            chunks: vec![],
+            upgrade: false,
+        }
+    }
+
+    pub fn json(status: StatusCode, value: serde_json::Value) -> Self {
+        MockResponse {
+            chunks: vec![Bytes::from(value.to_string())],
+            ..MockResponse::empty(status)
+        }
+    }
+
+    /// An error payload the way the Engine formats them.
+    pub fn error(status: StatusCode, message: &str) -> Self {
+        MockResponse::json(status, serde_json::json!({ "message": message }))
+    }
+
+    /// A streamed body, sent with chunked transfer encoding.
+    pub fn chunks<I, B>(status: StatusCode, chunks: I) -> Self
+    where
+        I: IntoIterator<Item = B>,
+        B: Into<Bytes>,
+    {
+        MockResponse {
+            chunks: chunks.into_iter().map(Into::into).collect(),
+            ..MockResponse::empty(status)
+        }
+    }
+
+    /// One JSON document per line, as in progress and event streams.
+    pub fn json_lines(status: StatusCode, values: Vec<serde_json::Value>) -> Self {
+        MockResponse::chunks(status, values.iter().map(|v| format!("{v}\n")))
+    }
+
+    /// Switch the connection to a raw stream, sending `chunks` on it.
+    pub fn upgrade<I, B>(chunks: I) -> Self
+    where
+        I: IntoIterator<Item = B>,
+        B: Into<Bytes>,
+    {
+        MockResponse {
+            upgrade: true,
+            ..MockResponse::chunks(StatusCode::SWITCHING_PROTOCOLS, chunks)
+        }
+    }
+
+    /// Write the chunks on the upgraded connection, then echo it.
+    async fn serve_upgraded(self, on_upgrade: OnUpgrade, state: Arc<Mutex<MockState>>) {
+        let Ok(upgraded) = on_upgrade.await else {
+            return;
+        };
+        let (mut read, mut write) = tokio::io::split(TokioIo::new(upgraded));
+        for chunk in self.chunks {
+            if write.write_all(&chunk).await.is_err() {
+                return;
+            }
+        }
+        let mut buf = [0; 4096];
+        while let Ok(read @ 1..) = read.read(&mut buf).await {
+            state.lock().unwrap().input.extend_from_slice(&buf[..read]);
+            if write.write_all(&buf[..read]).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn into_response(self) -> Response<BoxBody<Bytes, Infallible>> {
+        if self.upgrade {
+            return Response::builder()
+                .status(self.status)
+                .header(CONNECTION, "Upgrade")
+                .header(UPGRADE, "tcp")
+                .body(BodyExt::boxed(Empty::new()))
+                .unwrap();
+        }
+        let frames = self.chunks.into_iter().map(|c| Ok(Frame::data(c)));
+        let body = BodyExt::boxed(StreamBody::new(stream::iter(frames)));
+        Response::builder()
+            .status(self.status)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .unwrap()
+    }
+}
+
+/// Frame `message` with the 8-byte header of a multiplexed stream: the stream
+/// (0 stdin, 1 stdout, 2 stderr), 3 bytes of padding and the big-endian length.
+pub fn frame(stream: u8, message: &str) -> Bytes {
+    let mut frame = vec![stream, 0, 0, 0];
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(message.as_bytes());
+    Bytes::from(frame)
+}
+
+/// A request the server received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedRequest {
+    pub method: Method,
+    /// Path without the version prefix.
+    pub path: String,
+    /// The version prefix, such as `1.41`, if the path had one.
+    pub version: Option<String>,
+    pub query: Option<String>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    /// The decoded query string, as pairs.
+    pub fn query_pairs(&self) -> Vec<(String, String)> {
+        serde_urlencoded::from_str(self.query.as_deref().unwrap_or_default()).unwrap()
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap()
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    routes: Vec<(Method, String, MockResponse)>,
+    requests: Vec<RecordedRequest>,
+    /// Everything clients sent on upgraded connections.
+    input: Vec<u8>,
+}
+
+pub struct MockDocker {
+    state: Arc<Mutex<MockState>>,
+    /// `unix://` or `tcp://` address of the server.
+    host: String,
+    server: JoinHandle<()>,
+    // Removed with the socket when the server is dropped.
+    _dir: Option<TempDir>,
+}
+
+impl MockDocker {
+    pub fn start() -> MockDocker {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("docker.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let server = tokio::spawn({
+            let state = state.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(MockDocker::serve(stream, state.clone()));
+                }
+            }
+        });
+        MockDocker {
+            state,
+            host: format!("unix://{}", socket.display()),
+            server,
+            _dir: Some(dir),
+        }
+    }
+
+    /// Listen on a local TCP port instead of a Unix socket.
+    pub async fn start_tcp() -> MockDocker {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let server = tokio::spawn({
+            let state = state.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(MockDocker::serve(stream, state.clone()));
+                }
+            }
+        });
+        MockDocker {
+            state,
+            host: format!("tcp://{addr}"),
+            server,
+            _dir: None,
+        }
+    }
+
+    /// A client connected to this server.
+    pub fn docker(&self) -> Docker {
+        match self.host.starts_with("tcp://") {
+            true => Docker::connect_with_http(&self.host, 5, API_DEFAULT_VERSION),
+            false => Docker::connect_with_unix(&self.host, 5, API_DEFAULT_VERSION),
+        }
+        .unwrap()
+    }
+
+    /// Answer `method` requests to `path` with `response`. Later registrations
+    /// for the same route take precedence.
+    pub fn on(&self, method: Method, path: &str, response: MockResponse) -> &Self {
+        let mut state = self.state.lock().unwrap();
+        state.routes.insert(0, (method, path.to_string(), response));
+        self
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Everything clients sent on upgraded connections so far.
+    pub fn input(&self) -> String {
+        String::from_utf8_lossy(&self.state.lock().unwrap().input).to_string()
+    }
+
+    /// The only request received, failing if there were more or none.
+    pub fn request(&self) -> RecordedRequest {
+        let requests = self.requests();
+        assert_eq!(requests.len(), 1, "{requests:#?}");
+        requests[0].clone()
+    }
+
+    async fn serve<S>(stream: S, state: Arc<Mutex<MockState>>)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let service = service_fn(move |req| MockDocker::handle(state.clone(), req));
+        let _ = http1::Builder::new()
+            .serve_connection(TokioIo::new(stream), service)
+            .with_upgrades()
+            .await;
+    }
+
+    async fn handle(
+        state: Arc<Mutex<MockState>>,
+        mut req: Request<Incoming>,
+    ) -> Result<Response<BoxBody<Bytes, Infallible>>, hyper::Error> {
+        let on_upgrade = hyper::upgrade::on(&mut req);
+        let (parts, body) = req.into_parts();
+        let body = body.collect().await?.to_bytes();
+        let (version, path) = split_version(parts.uri.path());
+        let (version, path) = (version.map(str::to_string), path.to_string());
+        let response = {
+            let mut state = state.lock().unwrap();
+            state.requests.push(RecordedRequest {
+                method: parts.method.clone(),
+                path: path.clone(),
+                version,
+                query: parts.uri.query().map(str::to_string),
+                body: String::from_utf8_lossy(&body).to_string(),
+            });
+            state
+                .routes
+                .iter()
+                .find(|(method, route, _)| *method == parts.method && *route == path)
+                .map(|(_, _, response)| response.clone())
+                .unwrap_or_else(|| MockResponse::error(StatusCode::NOT_FOUND, "page not found"))
+        };
+        if response.upgrade {
+            tokio::spawn(response.clone().serve_upgraded(on_upgrade, state));
+        }
+        Ok(response.into_response())
+    }
+}
+
+impl Drop for MockDocker {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// `/v1.41/containers/json` -> `(Some("1.41"), "/containers/json")`
+fn split_version(path: &str) -> (Option<&str>, &str) {
+    let Some(rest) = path.strip_prefix("/v") else {
+        return (None, path);
+    };
+    match rest.find('/') {
+        Some(end) if rest[..end].chars().all(|c| c.is_ascii_digit() || c == '.') => {
+            (Some(&rest[..end]), &rest[end..])
+        }
+        _ => (None, path),
+    }
+}
@@ -0,0 +1,95 @@
+use std::{collections::HashMap, hash::Hash};
+
+use derive_new::new;
+use http::request::Builder;
+use http::Method;
+use http_body_util::Full;
+use hyper::body::Bytes;
+use serde_derive::Serialize;
+
+use bollard_stubs::models::*;
+
+use super::errors::Error;
+use super::Docker;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, new)]
+pub struct ListNetworksOptions<T>
+where
+    T: Into<String> + Eq + Hash + serde::Serialize,
+{
+    /// Filters to process on the network list, encoded as JSON. Available filters include:
+    ///  - `label=key` or `label="key=value"` of a network label
+    ///  - `name=<name>` a network's name
+    ///  - `id=<ID>` a network's ID
+    ///  - `type=`(`custom`|`builtin`)
+    #[serde(serialize_with = "crate::docker::utils::serialize_as_json")]
+    pub filters: HashMap<T, Vec<T>>,
+}
+
+impl Docker {
+    pub async fn list_networks<T>(
+        &self,
+        options: Option<ListNetworksOptions<T>>,
+    ) -> Result<Vec<Network>, Error>
+    where
+        T: Into<String> + Eq + Hash + serde::Serialize,
+    {
+        let req = self.build_request(
+            "/networks",
+            Builder::new().method(Method::GET),
+            options,
+            Ok(Full::new(Bytes::new())),
+        );
+        self.process_into_value(req).await
+    }
+
+    pub async fn remove_network(&self, network_name_or_id: &str) -> Result<(), Error> {
+        let path = format!("/networks/{network_name_or_id}");
+        let req = self.build_request(
+            &path,
+            Builder::new().method(Method::DELETE),
+            None::<String>,
+            Ok(Full::new(Bytes::new())),
+        );
+        self.process_into_unit(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::docker::mock::{MockDocker, MockResponse};
+
+    #[tokio::test]
+    async fn list_and_remove_networks() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::GET,
+            "/networks",
+            MockResponse::json(
+                StatusCode::OK,
+                json!([{ "Id": "n1", "Name": "nova-1-net", "Labels": { "nova": "" } }]),
+            ),
+        )
+        .on(
+            Method::DELETE,
+            "/networks/n1",
+            MockResponse::empty(StatusCode::NO_CONTENT),
+        );
+        let docker = mock.docker();
+        let options = ListNetworksOptions::new(HashMap::from([("label", vec!["nova"])]));
+        let networks = docker.list_networks(Some(options)).await.unwrap();
+        assert_eq!(networks[0].name.as_deref(), Some("nova-1-net"));
+        docker.remove_network("n1").await.unwrap();
+
+        let requests = mock.requests();
+        assert_eq!(
+            requests[0].query_pairs(),
+            vec![("filters".to_string(), r#"{"label":["nova"]}"#.to_string())]
+        );
+        assert_eq!(requests[1].method, Method::DELETE);
+    }
+}
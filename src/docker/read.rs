@@ -236,10 +236,7 @@ impl AsyncRead for StreamReader {
                         return Poll::Pending;
                     }
                     Poll::Ready(Some(Err(e))) => {
-                        return Poll::Ready(Err(io::Error::new(
-                            io::ErrorKind::Other,
-                            e.to_string(),
-                        )));
+                        return Poll::Ready(Err(io::Error::other(e.to_string())));
                     }
                 },
             }
@@ -310,6 +307,7 @@ pin_project! {
     }
 }
 
+#[allow(dead_code)]
 impl IncomingStream {
     pub(crate) fn new(incoming: Incoming) -> Self {
         Self { inner: incoming }
@@ -0,0 +1,281 @@
+use std::sync::atomic::Ordering;
+
+use derive_new::new;
+use futures_core::Stream;
+use http::request::Builder;
+use http::Method;
+use http_body_util::Full;
+use hyper::body::Bytes;
+use serde_derive::Serialize;
+
+use bollard_stubs::models::*;
+
+use super::errors::Error;
+use super::{ClientVersion, Docker};
+
+/// Kind of object an event is about.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+#[allow(dead_code)]
+pub enum EventType {
+    Container,
+    Image,
+    Volume,
+    Network,
+    Daemon,
+    Plugin,
+    Node,
+    Service,
+    Secret,
+    Config,
+}
+
+/// Which events to receive. Every kind of filter given must match, and any
+/// value of a kind may; an empty filter lets every event through.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct EventFilters {
+    #[serde(rename = "type", skip_serializing_if = "Vec::is_empty")]
+    pub types: Vec<EventType>,
+    /// Actions such as `die`, `oom` or `destroy`.
+    #[serde(rename = "event", skip_serializing_if = "Vec::is_empty")]
+    pub actions: Vec<String>,
+    /// Container names or ids.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub container: Vec<String>,
+    /// `key` or `key=value` labels of the object.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub label: Vec<String>,
+    /// Image names or ids.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub image: Vec<String>,
+    /// Volume names.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub volume: Vec<String>,
+    /// Network names or ids.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub network: Vec<String>,
+}
+
+impl EventFilters {
+    pub fn with_type(mut self, typ: EventType) -> Self {
+        self.types.push(typ);
+        self
+    }
+
+    pub fn with_action(mut self, action: &str) -> Self {
+        self.actions.push(action.to_string());
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_container(mut self, container_name_or_id: &str) -> Self {
+        self.container.push(container_name_or_id.to_string());
+        self
+    }
+
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label.push(label.to_string());
+        self
+    }
+
+    pub fn with_image(mut self, image: &str) -> Self {
+        self.image.push(image.to_string());
+        self
+    }
+
+    pub fn with_volume(mut self, volume: &str) -> Self {
+        self.volume.push(volume.to_string());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, new)]
+pub struct EventsOptions {
+    /// Replay the events since this UNIX timestamp before streaming new ones.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[new(default)]
+    pub since: Option<i64>,
+    /// End the stream at this UNIX timestamp instead of following it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[new(default)]
+    pub until: Option<i64>,
+    #[serde(serialize_with = "crate::docker::utils::serialize_as_json")]
+    pub filters: EventFilters,
+}
+
+impl Docker {
+    /// Version information of the daemon, including the range of API
+    /// versions it supports.
+    pub async fn version(&self) -> Result<SystemVersion, Error> {
+        let req = self.build_unversioned_request(
+            "/version",
+            Builder::new().method(Method::GET),
+            None::<String>,
+            Ok(Full::new(Bytes::new())),
+        );
+        self.process_into_value(req).await
+    }
+
+    /// Check the daemon is reachable; it answers `OK`.
+    #[allow(dead_code)]
+    pub async fn ping(&self) -> Result<String, Error> {
+        let req = self.build_unversioned_request(
+            "/_ping",
+            Builder::new().method(Method::GET),
+            None::<String>,
+            Ok(Full::new(Bytes::new())),
+        );
+        let res = self.process_request(req).await?;
+        Docker::decode_into_string(res).await
+    }
+
+    /// Stream what happens on the daemon as it happens: containers dying or
+    /// running out of memory, images pulled, volumes removed. The stream ends
+    /// at `until`, or only once dropped without it.
+    ///
+    /// It backs the operator's audit trail only: builds still learn that a
+    /// step stopped from its `wait_container` request, not from `die` events.
+    pub fn events(
+        &self,
+        options: Option<EventsOptions>,
+    ) -> impl Stream<Item = Result<SystemEventsResponse, Error>> {
+        let req = self.build_request(
+            "/events",
+            Builder::new().method(Method::GET),
+            options,
+            Ok(Full::new(Bytes::new())),
+        );
+        self.process_into_stream(req)
+    }
+
+    /// Downgrade the client to the daemon's API version when the daemon is
+    /// older, returning the version the client now speaks. Clones of this
+    /// client share the negotiated version.
+    pub async fn negotiate_version(&self) -> Result<ClientVersion, Error> {
+        let server_version: ClientVersion = self
+            .version()
+            .await?
+            .api_version
+            .ok_or(Error::APIVersionParseError {})?
+            .parse()?;
+        if server_version < self.client_version() {
+            self.version
+                .0
+                .store(server_version.major_version, Ordering::Relaxed);
+            self.version
+                .1
+                .store(server_version.minor_version, Ordering::Relaxed);
+        }
+        Ok(self.client_version())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::TryStreamExt;
+    use http::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::docker::mock::{MockDocker, MockResponse};
+    use crate::docker::API_DEFAULT_VERSION;
+
+    fn daemon(api_version: &str) -> MockDocker {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::GET,
+            "/version",
+            MockResponse::json(
+                StatusCode::OK,
+                json!({ "Version": "20.10.0", "ApiVersion": api_version, "MinAPIVersion": "1.12" }),
+            ),
+        );
+        mock
+    }
+
+    #[tokio::test]
+    async fn ping_is_unversioned() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::GET,
+            "/_ping",
+            MockResponse::chunks(StatusCode::OK, ["OK"]),
+        );
+        assert_eq!(mock.docker().ping().await.unwrap(), "OK");
+        let req = mock.request();
+        assert_eq!(req.path, "/_ping");
+        assert_eq!(req.version, None);
+    }
+
+    #[tokio::test]
+    async fn negotiates_down_to_older_daemons() {
+        let mock = daemon("1.41");
+        let docker = mock.docker();
+        let clone = docker.clone();
+        let version = docker.negotiate_version().await.unwrap();
+        assert_eq!(version.to_string(), "1.41");
+        assert_eq!(clone.client_version(), version);
+
+        let _ = clone.inspect_image("alpine").await;
+        let requests = mock.requests();
+        assert_eq!(requests[0].version, None);
+        assert_eq!(requests[1].version.as_deref(), Some("1.41"));
+    }
+
+    #[tokio::test]
+    async fn keeps_client_version_for_newer_daemons() {
+        let mock = daemon("1.45");
+        let version = mock.docker().negotiate_version().await.unwrap();
+        assert_eq!(version, *API_DEFAULT_VERSION);
+    }
+
+    #[tokio::test]
+    async fn rejects_unparseable_versions() {
+        let mock = daemon("latest");
+        let err = mock.docker().negotiate_version().await.unwrap_err();
+        assert!(matches!(err, Error::APIVersionParseError {}));
+    }
+
+    #[tokio::test]
+    async fn events_are_streamed_with_typed_filters() {
+        let mock = MockDocker::start();
+        let event = |action: &str, time| {
+            json!({
+                "Type": "container",
+                "Action": action,
+                "Actor": { "ID": "abc", "Attributes": { "nova.step": "build", "exitCode": "137" } },
+                "time": time,
+            })
+        };
+        mock.on(
+            Method::GET,
+            "/events",
+            MockResponse::json_lines(StatusCode::OK, vec![event("oom", 10), event("die", 11)]),
+        );
+        let filters = EventFilters::default()
+            .with_type(EventType::Container)
+            .with_action("die")
+            .with_action("oom")
+            .with_label("nova");
+        let mut options = EventsOptions::new(filters);
+        options.since = Some(10);
+        let events: Vec<_> = mock
+            .docker()
+            .events(Some(options))
+            .try_collect()
+            .await
+            .unwrap();
+        let actions: Vec<_> = events.iter().map(|e| e.action.as_deref()).collect();
+        assert_eq!(actions, vec![Some("oom"), Some("die")]);
+        let actor = events[1].actor.as_ref().unwrap();
+        assert_eq!(actor.id.as_deref(), Some("abc"));
+
+        let query = mock.request().query_pairs();
+        assert_eq!(query[0], ("since".to_string(), "10".to_string()));
+        assert_eq!(query[1].0, "filters");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&query[1].1).unwrap(),
+            json!({ "type": ["container"], "event": ["die", "oom"], "label": ["nova"] })
+        );
+    }
+}
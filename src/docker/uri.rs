@@ -20,27 +20,30 @@ impl<'a> TryFrom<Uri<'a>> for HyperUri {
 }
 
 impl<'a> Uri<'a> {
+    /// Without a `client_version` the path is left unversioned, which the
+    /// daemon answers with its own default version.
     pub(crate) fn parse<T>(
         socket: &'a str,
         client_type: &ClientType,
         path: &'a str,
         query: Option<T>,
-        client_version: &ClientVersion,
+        client_version: Option<&ClientVersion>,
     ) -> Result<Self, Error>
     where
         T: serde::ser::Serialize,
     {
+        let version = client_version
+            .map(|v| format!("/v{}.{}", v.major_version, v.minor_version))
+            .unwrap_or_default();
         //unix://
         let host_str = format!(
-            "{}://{}/v{}.{}{}",
+            "{}://{}{}{}",
             Uri::socket_scheme(client_type),
             Uri::socket_host(socket, client_type),
-            client_version.major_version,
-            client_version.minor_version,
+            version,
             path
         );
         let mut url = Url::parse(host_str.as_ref())?;
-        url = url.join(path)?;
 
         if let Some(pairs) = query {
             let qs = serde_urlencoded::to_string(pairs)?;
@@ -58,12 +61,18 @@ impl<'a> Uri<'a> {
     {
         match client_type {
             ClientType::Unix => hex::encode(socket.as_ref().to_string_lossy().as_bytes()),
+            ClientType::Http => socket.as_ref().to_string_lossy().into_owned(),
+            #[cfg(feature = "ssl")]
+            ClientType::Ssl => socket.as_ref().to_string_lossy().into_owned(),
         }
     }
 
     fn socket_scheme(client_type: &ClientType) -> &'a str {
         match client_type {
             ClientType::Unix => "unix",
+            ClientType::Http => "http",
+            #[cfg(feature = "ssl")]
+            ClientType::Ssl => "https",
         }
     }
 }
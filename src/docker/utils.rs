@@ -3,8 +3,8 @@ use std::fmt;
 use hyper::body::Bytes;
 use serde::Serialize;
 
-/// Result type for the [Logs API](Docker::logs())
-#[derive(Debug, Clone, PartialEq)]
+/// Result type for the [Logs API](super::Docker::logs())
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[allow(missing_docs)]
 pub enum LogOutput {
     StdErr { message: Bytes },
@@ -38,6 +38,7 @@ impl AsRef<[u8]> for LogOutput {
 
 impl LogOutput {
     /// Get the raw bytes of the output
+    #[allow(dead_code)]
     pub fn into_bytes(self) -> Bytes {
         match self {
             LogOutput::StdErr { message } => message,
@@ -58,6 +59,7 @@ where
     )
 }
 
+#[allow(dead_code)]
 pub(crate) fn serialize_join_newlines<S>(t: &[&str], s: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
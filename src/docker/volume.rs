@@ -0,0 +1,166 @@
+use std::{collections::HashMap, hash::Hash};
+
+use derive_new::new;
+use http::request::Builder;
+use http::Method;
+use http_body_util::Full;
+use hyper::body::Bytes;
+use serde_derive::Serialize;
+
+use bollard_stubs::models::*;
+
+use super::errors::Error;
+use super::Docker;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, new)]
+#[serde(rename_all = "PascalCase")]
+pub struct CreateVolumeOptions<T>
+where
+    T: Into<String> + Eq + Hash + serde::Serialize,
+{
+    /// The new volume's name. If not specified, Docker generates a name.
+    pub name: T,
+    /// Name of the volume driver to use.
+    pub driver: T,
+    /// A mapping of driver options and values. These options are passed directly to the driver
+    /// and are driver specific.
+    pub driver_opts: HashMap<T, T>,
+    /// User-defined key/value metadata.
+    pub labels: HashMap<T, T>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, new)]
+pub struct ListVolumesOptions<T>
+where
+    T: Into<String> + Eq + Hash + serde::Serialize,
+{
+    /// Filters to process on the volume list, encoded as JSON. Available filters include:
+    ///  - `label=key` or `label="key=value"` of a volume label
+    ///  - `name=<name>` a volume's name
+    ///  - `dangling=`(`true`|`false`) volumes not in use by a container
+    #[serde(serialize_with = "crate::docker::utils::serialize_as_json")]
+    pub filters: HashMap<T, Vec<T>>,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, new)]
+pub struct RemoveVolumeOptions {
+    /// Force the removal of the volume.
+    pub force: bool,
+}
+
+impl Docker {
+    pub async fn create_volume<T>(&self, config: CreateVolumeOptions<T>) -> Result<Volume, Error>
+    where
+        T: Into<String> + Eq + Hash + serde::Serialize,
+    {
+        let url = "/volumes/create";
+        let req = self.build_request(
+            url,
+            Builder::new().method(Method::POST),
+            None::<String>,
+            Docker::serialize_payload(Some(config)),
+        );
+        self.process_into_value(req).await
+    }
+
+    pub async fn list_volumes<T>(
+        &self,
+        options: Option<ListVolumesOptions<T>>,
+    ) -> Result<VolumeListResponse, Error>
+    where
+        T: Into<String> + Eq + Hash + serde::Serialize,
+    {
+        let req = self.build_request(
+            "/volumes",
+            Builder::new().method(Method::GET),
+            options,
+            Ok(Full::new(Bytes::new())),
+        );
+        self.process_into_value(req).await
+    }
+
+    pub async fn remove_volume(
+        &self,
+        volume_name: &str,
+        options: Option<RemoveVolumeOptions>,
+    ) -> Result<(), Error> {
+        let path = format!("/volumes/{volume_name}");
+        let req = self.build_request(
+            &path,
+            Builder::new().method(Method::DELETE),
+            options,
+            Ok(Full::new(Bytes::new())),
+        );
+        self.process_into_unit(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::docker::mock::{MockDocker, MockResponse};
+
+    #[tokio::test]
+    async fn create_and_remove_volume() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::POST,
+            "/volumes/create",
+            MockResponse::json(
+                StatusCode::CREATED,
+                serde_json::to_value(Volume {
+                    name: "nova-1-workspace".to_string(),
+                    driver: "local".to_string(),
+                    scope: Some(VolumeScopeEnum::LOCAL),
+                    ..Default::default()
+                })
+                .unwrap(),
+            ),
+        )
+        .on(
+            Method::DELETE,
+            "/volumes/nova-1-workspace",
+            MockResponse::empty(StatusCode::NO_CONTENT),
+        );
+        let docker = mock.docker();
+        let options = CreateVolumeOptions::new(
+            "nova-1-workspace",
+            "local",
+            HashMap::new(),
+            HashMap::from([("nova", "")]),
+        );
+        let volume = docker.create_volume(options).await.unwrap();
+        assert_eq!(volume.name, "nova-1-workspace");
+        docker
+            .remove_volume("nova-1-workspace", Some(RemoveVolumeOptions::new(true)))
+            .await
+            .unwrap();
+        let requests = mock.requests();
+        assert_eq!(
+            requests[0].json(),
+            json!({
+                "Name": "nova-1-workspace",
+                "Driver": "local",
+                "DriverOpts": {},
+                "Labels": { "nova": "" },
+            })
+        );
+        assert_eq!(requests[1].query.as_deref(), Some("force=true"));
+    }
+
+    #[tokio::test]
+    async fn list_volumes_accepts_null_volumes() {
+        let mock = MockDocker::start();
+        mock.on(
+            Method::GET,
+            "/volumes",
+            MockResponse::json(StatusCode::OK, json!({ "Volumes": null, "Warnings": null })),
+        );
+        let options = ListVolumesOptions::new(HashMap::from([("label", vec!["nova"])]));
+        let volumes = mock.docker().list_volumes(Some(options)).await.unwrap();
+        assert!(volumes.volumes.is_empty());
+    }
+}
@@ -1,98 +1,297 @@
-use core::{build::*, *};
+use core::{
+    build::*,
+    events::BuildEvent,
+    file::DEFAULT_PIPELINE_FILE,
+    janitor::{Janitor, Sweep, DEFAULT_JANITOR_INTERVAL, DEFAULT_JANITOR_MAX_AGE},
+    secrets::Secrets,
+    *,
+};
 use docker::{
-    container::{CreateContainerConfig, CreateContainerOptions, StartContainerOptions},
+    errors::Error,
+    system::{EventFilters, EventType, EventsOptions},
     Docker,
 };
 
-use nonempty::nonempty;
-use std::{collections::HashMap, time::Duration};
+use futures_util::{stream, StreamExt};
+use std::{env, process, time::Duration};
+use tokio::{signal, sync::broadcast, time};
+use tokio_util::sync::CancellationToken;
 
 mod core;
 mod docker;
 
 #[tokio::main]
 async fn main() {
-    let conn = Docker::connect_with_unix_defaults().unwrap();
+    let path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_PIPELINE_FILE.to_string());
+    let pl = match Pipeline::from_file(&path) {
+        Ok(pl) => pl,
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    };
+    if let Err(errs) = pl.validate() {
+        eprintln!("{errs}");
+        process::exit(1);
+    }
+
+    let secrets = match env::var("CI_RS_SECRETS_FILE") {
+        Ok(path) => match Secrets::from_file(path) {
+            Ok(secrets) => secrets,
+            Err(err) => {
+                eprintln!("{err}");
+                process::exit(1);
+            }
+        },
+        Err(_) => Secrets::default(),
+    }
+    .or(Secrets::from_env(pl.secret_names()));
+    let max_parallelism = env::var("CI_RS_MAX_PARALLELISM")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_PARALLELISM);
+    let janitor_interval = env::var("CI_RS_JANITOR_INTERVAL")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_JANITOR_INTERVAL);
+    // Sweeping with no pause in between would keep the daemon busy.
+    if janitor_interval.is_zero() {
+        eprintln!("CI_RS_JANITOR_INTERVAL must be at least 1 second");
+        process::exit(1);
+    }
+    let janitor_dry_run = env::var("CI_RS_JANITOR_DRY_RUN").is_ok_and(|v| !v.is_empty());
+    let audit = env::var("CI_RS_AUDIT").is_ok_and(|v| !v.is_empty());
+    let janitor_max_age = env::var("CI_RS_JANITOR_MAX_AGE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_JANITOR_MAX_AGE);
+    let runner = env::var("CI_RS_RUNNER_ID")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .map_or_else(RunnerId::generate, |v| RunnerId::from(v.as_str()));
+
+    let conn = match Docker::connect_with_defaults() {
+        Ok(conn) => conn,
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    };
+    if let Err(err) = conn.negotiate_version().await {
+        eprintln!("{err}");
+        process::exit(1);
+    }
+    let cancellation = CancellationToken::new();
+    let mut b = Build::new(pl, BuildState::BuildReady, vec![] as CompletedSteps)
+        .with_max_parallelism(max_parallelism)
+        .with_secrets(secrets)
+        .with_runner(runner)
+        .with_cancellation(cancellation.clone());
+
+    // Clear out what crashed runs left behind, now and while the build runs.
+    let janitor = Janitor::new()
+        .with_runner(b.runner.clone())
+        .with_active(b.id.clone())
+        .with_max_age(janitor_max_age)
+        .with_dry_run(janitor_dry_run);
+    print_sweep(janitor.sweep(&conn).await, janitor.dry_run);
+    tokio::spawn({
+        let conn = conn.clone();
+        async move {
+            loop {
+                time::sleep(janitor_interval).await;
+                print_sweep(janitor.sweep(&conn).await, janitor.dry_run);
+            }
+        }
+    });
+    if audit {
+        let mut images: Vec<String> = b
+            .pipeline
+            .steps
+            .iter()
+            .map(|step| match step.image.reference() {
+                // Only digests contain a colon.
+                (repository, digest) if digest.contains(':') => format!("{repository}@{digest}"),
+                (repository, tag) => format!("{repository}:{tag}"),
+            })
+            .collect();
+        images.sort();
+        images.dedup();
+        tokio::spawn(print_audit(
+            conn.clone(),
+            b.runner.clone(),
+            b.workspace_name(),
+            images,
+        ));
+    }
+    tokio::spawn(async move {
+        if signal::ctrl_c().await.is_ok() {
+            cancellation.cancel();
+        }
+    });
+    let printer = tokio::spawn(print_events(b.subscribe()));
+    let (result, completed_steps) = b.run(&conn).await;
+    let _ = printer.await;
+    for step in &completed_steps {
+        for attempt in &step.attempts {
+            let command = b
+                .pipeline
+                .steps
+                .iter()
+                .find(|s| s.name == step.name)
+                .zip(attempt.failed_command)
+                .and_then(|(s, index)| Some((index, s.commands.get(index)?)));
+            if let Some((index, command)) = command {
+                println!(
+                    "==> {} failed at command #{}: {}",
+                    step.name.0,
+                    index + 1,
+                    command
+                );
+            }
+        }
+    }
+    if let Some(summary) = b.summary() {
+        println!("==> {summary}");
+    }
+    if result != BuildResult::BuildSucceeded {
+        process::exit(1);
+    }
+}
 
-    let steps = nonempty![
-        //
-        Step::new(
-            "build".into(),
-            nonempty!["dat".to_string()],
-            "ubuntu:20.04".into(),
-            None
-        ),
-        Step::new(
-            "test".into(),
-            // nonempty!["sleep 10s && date".to_string()],
-            nonempty!["ps".to_string()],
-            "ubuntu:20.04".into(),
-            Some(vec!["test".into()])
-        ),
-        Step::new(
-            "deploy".into(),
-            // nonempty!["sleep 10s && date".to_string()],
-            nonempty!["uname".to_string()],
-            "ubuntu:20.04".into(),
-            Some(vec!["test".into()])
-        ),
+fn print_sweep(sweep: Result<Sweep, Error>, dry_run: bool) {
+    let sweep = match sweep {
+        Ok(sweep) => sweep,
+        Err(err) => return eprintln!("==> janitor: {err}"),
+    };
+    for resource in &sweep.removed {
+        match dry_run {
+            true => println!("==> janitor: would remove {resource}"),
+            false => println!("==> janitor: removed {resource}"),
+        }
+    }
+    for (resource, err) in &sweep.failed {
+        eprintln!("==> janitor: could not remove {resource}: {err}");
+    }
+}
+
+/// Print everything the daemon reports doing to the resources of `runner`, as
+/// it happens, for operators to audit: its labelled containers, the build's
+/// `workspace` volume and the pulls of `images`. What the janitor removes for
+/// other runners is printed by the sweeps instead.
+async fn print_audit(conn: Docker, runner: RunnerId, workspace: String, images: Vec<String>) {
+    // Docker requires every kind of filter to match, so each resource type is
+    // followed on a stream of its own.
+    let mut filters = vec![
+        EventFilters::default().with_label(&format!("{RUNNER_ID_LABEL}={}", runner.0)),
+        // Volume events do not carry the volume's labels.
+        EventFilters::default()
+            .with_type(EventType::Volume)
+            .with_volume(&workspace),
     ];
-    let pl = Pipeline::new(steps);
-    let mut b = Build::new(pl, BuildState::BuildReady, vec![] as CompletedSteps);
+    if !images.is_empty() {
+        let pulls = EventFilters::default()
+            .with_type(EventType::Image)
+            .with_action("pull");
+        filters.push(images.iter().fold(pulls, |f, image| f.with_image(image)));
+    }
+    let streams = filters
+        .into_iter()
+        .map(|filters| Box::pin(conn.events(Some(EventsOptions::new(filters)))));
+    let mut events = stream::select_all(streams);
+    while let Some(event) = events.next().await {
+        let event = match event {
+            Ok(event) => event,
+            Err(err) => return eprintln!("==> audit: {err}"),
+        };
+        let actor = event.actor.unwrap_or_default();
+        let name = actor
+            .attributes
+            .and_then(|mut attributes| attributes.remove("name"))
+            .or(actor.id)
+            .unwrap_or_default();
+        eprintln!(
+            "==> audit: {} {} {name}",
+            event.typ.unwrap_or_default(),
+            event.action.unwrap_or_default()
+        );
+    }
+}
+
+/// Print the build's progress as it happens, until it finishes.
+async fn print_events(mut events: broadcast::Receiver<BuildEvent>) {
+    let mut started = None;
     loop {
-        b.progress(&conn).await;
-        match b.state {
-            BuildState::BuildFinished(_res) => {
-                break;
-            }
-            _ => {
-                tokio::time::sleep(Duration::from_secs(1)).await;
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                println!("==> fell behind, {missed} events missed: step output is incomplete");
                 continue;
             }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let started = *started.get_or_insert(event.at());
+        let elapsed = event.at().duration_since(started).unwrap_or_default();
+        match event {
+            BuildEvent::BuildStarted { build, .. } => println!("==> build {}", build.0),
+            BuildEvent::BuildRejected { reason, .. } => eprintln!("==> {reason}"),
+            BuildEvent::StepPull {
+                step,
+                image,
+                layer: Some(layer),
+                status,
+                ..
+            } => println!("==> [{elapsed:.1?}] {} {image}: {layer}: {status}", step.0),
+            BuildEvent::StepPull {
+                step,
+                image,
+                status,
+                ..
+            } => println!("==> [{elapsed:.1?}] {} {image}: {status}", step.0),
+            BuildEvent::StepStarted { step, attempt, .. } if attempt > 1 => {
+                println!("==> [{elapsed:.1?}] {} attempt #{attempt}", step.0)
+            }
+            BuildEvent::StepStarted { step, .. } => println!("==> [{elapsed:.1?}] {}", step.0),
+            BuildEvent::StepLog { step, line, .. } => print!("{} | {line}", step.0),
+            BuildEvent::StepRetrying {
+                step,
+                attempt,
+                exit_code,
+                delay,
+                ..
+            } => println!(
+                "==> [{elapsed:.1?}] {} attempt #{attempt} failed with exit code {}, retrying in {delay:.1?}",
+                step.0, exit_code.0
+            ),
+            BuildEvent::StepFinished {
+                step,
+                result,
+                attempts,
+                ..
+            } if attempts > 1 => println!(
+                "==> [{elapsed:.1?}] {} {:?} after {attempts} attempts",
+                step.0, result
+            ),
+            BuildEvent::StepFinished { step, result, .. } => {
+                println!("==> [{elapsed:.1?}] {} {:?}", step.0, result)
+            }
+            BuildEvent::BuildTimedOut { .. } => println!("==> [{elapsed:.1?}] build timed out"),
+            BuildEvent::DockerError {
+                step: Some(step),
+                error,
+                ..
+            } => eprintln!("==> [{elapsed:.1?}] {}: docker: {error}", step.0),
+            BuildEvent::DockerError { error, .. } => {
+                eprintln!("==> [{elapsed:.1?}] docker: {error}")
+            }
+            BuildEvent::BuildFinished { result, .. } => {
+                println!("==> [{elapsed:.1?}] build {:?}", result);
+                return;
+            }
         }
     }
-    println!("{:?}", b.completed_steps);
 }
-// #[cfg(test)]
-// mod tests {
-//     use std::collections::HashMap;
-
-//     use super::core::{build::*, *};
-//     use nonempty::nonempty;
-//     // #[test]
-//     fn test_pipeline() -> Pipeline {
-//         let steps = nonempty![
-//             //
-//             Step::new(
-//                 "First Step".to_string(),
-//                 "ubuntu".to_string(),
-//                 nonempty!["date".to_string()],
-//             ),
-//             Step::new(
-//                 "Second Step".to_string(),
-//                 "ubuntu".to_string(),
-//                 nonempty!["uname -r".to_string()],
-//             ),
-//         ];
-//         let pl = Pipeline::new(steps);
-//         pl
-//     }
-//     #[test]
-//     fn test_build() {
-//         // let mut b = Build::new(
-//         //     test_pipeline(),
-//         //     BuildState::BuildReady,
-//         //     HashMap::new() as CompletedSteps,
-//         // );
-//         // b.progress();
-//         // // println!("{:?}", b.state);
-//         // assert_eq!(
-//         //     b.state,
-//         //     BuildState::BuildRunning(BuildRunningState {
-//         //         step: StepName("First Step".to_string())
-//         //     })
-//         // );
-//         // b.progress();
-//         // assert_eq!(b.state, BuildState::BuildReady)
-//     }
-// }